@@ -3,7 +3,7 @@ pub use masof::*;
 use futures::StreamExt;
 use futures::{select, FutureExt};
 use futures_timer::Delay;
-use std::io::{stdout, Stdout};
+use std::io::{stdout, BufWriter, Stdout};
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 use structopt::StructOpt;
@@ -55,7 +55,6 @@ enum Mode {
 
 struct Main {
     main_mode_map: KeyMap<MainAction>,
-    edit_mode_map: KeyMap<MainAction>,
     leave: bool,
     renderer: Renderer,
     start_time: Instant,
@@ -70,13 +69,15 @@ impl Main {
             renderer.set_bottom_screen(bottom as u16);
         }
 
+        let mut read_line = ReadLine::new();
+        read_line.set_prompt(":");
+
         Ok(Self {
             leave: false,
             main_mode_map: KeyMap::new(),
-            edit_mode_map: KeyMap::new(),
             renderer,
             start_time: Instant::now(),
-            read_line: ReadLine::new(),
+            read_line,
             mode: Mode::Main,
         })
     }
@@ -85,15 +86,12 @@ impl Main {
         let m = &mut self.main_mode_map;
         m.add_no_mods(KeyCode::Char('q'), MainAction::Quit);
         m.add_no_mods(KeyCode::Enter, MainAction::Edit);
-
-        let m = &mut self.edit_mode_map;
-        m.add_no_mods(KeyCode::Enter, MainAction::Main);
     }
 
     async fn run(mut self) -> Result<(), Error> {
         self.init_key_maps();
 
-        let mut stdout = stdout();
+        let mut stdout = BufWriter::with_capacity(0x10000, stdout());
 
         self.renderer.term_on(&mut stdout)?;
         let r = self.event_loop(&mut stdout).await;
@@ -102,7 +100,7 @@ impl Main {
         r
     }
 
-    async fn event_loop(&mut self, stdout: &mut Stdout) -> Result<(), Error> {
+    async fn event_loop(&mut self, stdout: &mut BufWriter<Stdout>) -> Result<(), Error> {
         let mut reader = crossterm::event::EventStream::new();
 
         self.redraw(stdout)?;
@@ -114,7 +112,7 @@ impl Main {
                 _ = delay.fuse() => {  },
                 maybe_event = reader.next().fuse() => {
                     if let Some(Ok(event)) = &maybe_event {
-                        self.renderer.event(&event);
+                        self.renderer.event(event);
                     }
                     match maybe_event {
                         Some(Ok(Event::Mouse{..})) => continue,
@@ -150,34 +148,30 @@ impl Main {
     }
 
     fn on_event(&mut self, event: crossterm::event::Event) -> Result<(), Error> {
-        match event {
-            Event::Key(event) => match self.mode {
+        if let Event::Key(event) = event {
+            match self.mode {
                 Mode::Main => {
-                    let action = self.main_mode_map.get_action(event).map(|x| x.clone());
-                    match action {
-                        Some(action) => self.main_action(action)?,
-                        None => {}
+                    let action = self.main_mode_map.get_action(event).cloned();
+                    if let Some(action) = action {
+                        self.main_action(action)?
                     }
                 }
-                Mode::Edit => {
-                    if let Some(action) = ReadLine::def_key_map().get_action(event) {
-                        self.read_line.apply_action(action, event);
-                    } else {
-                        let action = self.edit_mode_map.get_action(event).map(|x| x.clone());
-                        match action {
-                            Some(action) => self.main_action(action)?,
-                            None => {}
-                        }
+                Mode::Edit => match self.read_line.handle_key(event) {
+                    Some(readline::Outcome::Submitted(line)) => {
+                        self.read_line.push_history(&line);
+                        self.read_line.clear();
+                        self.main_action(MainAction::Main)?
                     }
-                }
-            },
-            _ => {}
+                    Some(readline::Outcome::Cancelled) => self.main_action(MainAction::Main)?,
+                    _ => {}
+                },
+            }
         }
 
         Ok(())
     }
 
-    fn redraw(&mut self, stdout: &mut Stdout) -> Result<(), Error> {
+    fn redraw(&mut self, stdout: &mut BufWriter<Stdout>) -> Result<(), Error> {
         self.renderer.begin()?;
         self.renderer.draw(
             10,
@@ -240,18 +234,14 @@ impl Main {
 
         if let Mode::Edit = self.mode {
             let l = self.renderer.height() - 1;
-            self.renderer
-                .draw(0, l, &ansi_term::Style::default().paint(":"));
-            let pos = (1, l);
-            self.read_line.draw(
-                pos.0,
-                pos.1,
-                30,
+            let column = self.read_line.draw(
+                0,
+                l,
+                31,
                 &mut self.renderer,
                 ReadLine::def_style_map(),
             );
-            self.renderer
-                .set_cursor(Some((pos.0 + self.read_line.get_cursor(), pos.1)));
+            self.renderer.set_cursor(Some((column, l)));
         } else {
             self.renderer.set_cursor(None);
         }
@@ -0,0 +1,463 @@
+//! Output abstraction for the renderer
+
+use super::renderer::Error;
+use crossterm::{
+    cursor,
+    cursor::MoveTo,
+    style::{
+        Attribute, Attributes, Color, ContentStyle, Print, ResetColor, SetAttribute,
+        SetAttributes, SetBackgroundColor, SetForegroundColor,
+    },
+    terminal, Command, QueueableCommand,
+};
+use std::io::Write;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Where the renderer sends its output. Every `Write` is a backend that
+/// emits crossterm commands to it.
+///
+/// Commands are written one at a time, so wrap a terminal in a `BufWriter`
+/// (e.g. `BufWriter::with_capacity(0x10000, stdout())`) to have each frame
+/// reach it in a single write when flushed.
+pub trait Backend {
+    fn move_to(&mut self, x: u16, y: u16) -> Result<(), Error>;
+    fn set_fg(&mut self, color: Color) -> Result<(), Error>;
+    fn set_bg(&mut self, color: Color) -> Result<(), Error>;
+    fn set_attrs(&mut self, attributes: Attributes) -> Result<(), Error>;
+    /// Clear all attributes, including colors.
+    fn reset_attrs(&mut self) -> Result<(), Error>;
+    /// Reset the colors to the terminal's defaults. Terminals can only do
+    /// so together with the attributes, so those are cleared too.
+    fn reset_color(&mut self) -> Result<(), Error>;
+    fn print(&mut self, s: &str) -> Result<(), Error>;
+    fn show_cursor(&mut self) -> Result<(), Error>;
+    fn hide_cursor(&mut self) -> Result<(), Error>;
+    fn flush(&mut self) -> Result<(), Error>;
+
+    /// Queue a terminal command not covered by the methods above.
+    fn command(&mut self, command: impl Command) -> Result<(), Error>;
+
+    fn size(&self) -> Result<(u16, u16), Error> {
+        Ok(terminal::size()?)
+    }
+
+    fn cursor_position(&self) -> Result<(u16, u16), Error> {
+        Ok(cursor::position()?)
+    }
+
+    fn enable_raw_mode(&mut self) -> Result<(), Error> {
+        Ok(terminal::enable_raw_mode()?)
+    }
+
+    fn disable_raw_mode(&mut self) -> Result<(), Error> {
+        Ok(terminal::disable_raw_mode()?)
+    }
+}
+
+impl<W: Write> Backend for W {
+    fn move_to(&mut self, x: u16, y: u16) -> Result<(), Error> {
+        self.command(MoveTo(x, y))
+    }
+
+    fn set_fg(&mut self, color: Color) -> Result<(), Error> {
+        self.command(SetForegroundColor(color))
+    }
+
+    fn set_bg(&mut self, color: Color) -> Result<(), Error> {
+        self.command(SetBackgroundColor(color))
+    }
+
+    fn set_attrs(&mut self, attributes: Attributes) -> Result<(), Error> {
+        self.command(SetAttributes(attributes))
+    }
+
+    fn reset_attrs(&mut self) -> Result<(), Error> {
+        self.command(SetAttribute(Attribute::Reset))
+    }
+
+    fn reset_color(&mut self) -> Result<(), Error> {
+        self.command(ResetColor)
+    }
+
+    fn print(&mut self, s: &str) -> Result<(), Error> {
+        self.command(Print(s))
+    }
+
+    fn show_cursor(&mut self) -> Result<(), Error> {
+        self.command(cursor::Show)
+    }
+
+    fn hide_cursor(&mut self) -> Result<(), Error> {
+        self.command(cursor::Hide)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(Write::flush(self)?)
+    }
+
+    fn command(&mut self, command: impl Command) -> Result<(), Error> {
+        self.queue(command)?;
+        Ok(())
+    }
+}
+
+/// Backend that composes the output into an in-memory screen, for
+/// asserting what was rendered without a terminal.
+pub struct TestBackend {
+    width: u16,
+    height: u16,
+    /// `None` marks the second column of a wide character
+    cells: Vec<Vec<Option<(char, ContentStyle)>>>,
+    cursor: (u16, u16),
+    cursor_visible: bool,
+    raw_mode: bool,
+    style: ContentStyle,
+    /// Whether printing into the last column moves the cursor to the next
+    /// line, scrolling at the bottom, as terminals without a pending-wrap
+    /// state do. Turned off and on by DECAWM.
+    auto_wrap: bool,
+    /// Every escape sequence and character written, as a terminal would see it
+    output: String,
+}
+
+impl TestBackend {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![blank_row(width); height as usize],
+            cursor: (0, 0),
+            cursor_visible: true,
+            raw_mode: false,
+            style: ContentStyle::default(),
+            auto_wrap: true,
+            output: String::new(),
+        }
+    }
+
+    /// Place the cursor, as if the shell left it there before `term_on`.
+    pub fn set_cursor_position(&mut self, x: u16, y: u16) {
+        self.cursor = (x, y);
+    }
+
+    /// Change the screen size, keeping the top left of what was on it, as a
+    /// terminal window being resized would.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        for row in &mut self.cells {
+            row.resize(width as usize, Some((' ', ContentStyle::default())));
+        }
+        self.cells.resize(height as usize, blank_row(width));
+        self.width = width;
+        self.height = height;
+        self.cursor.0 = self.cursor.0.min(width.saturating_sub(1));
+        self.cursor.1 = self.cursor.1.min(height.saturating_sub(1));
+    }
+
+    pub fn cursor(&self) -> (u16, u16) {
+        self.cursor
+    }
+
+    pub fn is_cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    pub fn is_raw_mode(&self) -> bool {
+        self.raw_mode
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn cell(&self, x: u16, y: u16) -> Option<(char, ContentStyle)> {
+        *self.cells.get(y as usize)?.get(x as usize)?
+    }
+
+    /// The characters of `len` columns starting at `(x, y)`.
+    pub fn get_string(&self, x: u16, y: u16, len: u16) -> String {
+        let row = match self.cells.get(y as usize) {
+            Some(row) => row,
+            None => return String::new(),
+        };
+        row.iter()
+            .skip(x as usize)
+            .take(len as usize)
+            .flatten()
+            .map(|(c, _)| c)
+            .collect()
+    }
+
+    pub fn to_lines(&self) -> Vec<String> {
+        (0..self.height)
+            .map(|y| self.get_string(0, y, self.width))
+            .collect()
+    }
+
+    fn log(&mut self, command: impl Command) {
+        let _ = command.write_ansi(&mut self.output);
+    }
+
+    /// Apply the erase sequences of `Clear`, which only reach the backend
+    /// as generic commands.
+    fn erase(&mut self, ansi: &str) {
+        let (x, y) = (self.cursor.0 as usize, self.cursor.1 as usize);
+        let blank = Some((' ', ContentStyle::default()));
+        let rows = self.cells.len();
+        let range = match ansi {
+            "\x1b[2J" => (0, 0)..(rows, 0),
+            "\x1b[J" => (y, x)..(rows, 0),
+            "\x1b[1J" => (0, 0)..(y, x + 1),
+            "\x1b[2K" => (y, 0)..(y + 1, 0),
+            "\x1b[K" => (y, x)..(y + 1, 0),
+            _ => return,
+        };
+        for (row, cells) in self.cells.iter_mut().enumerate() {
+            for (column, cell) in cells.iter_mut().enumerate() {
+                if (row, column) >= range.start && (row, column) < range.end {
+                    *cell = blank;
+                }
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.cursor.0 = 0;
+        if self.cursor.1 + 1 < self.height {
+            self.cursor.1 += 1;
+        } else {
+            self.cells.remove(0);
+            self.cells.push(blank_row(self.width));
+        }
+    }
+}
+
+fn blank_row(width: u16) -> Vec<Option<(char, ContentStyle)>> {
+    vec![Some((' ', ContentStyle::default())); width as usize]
+}
+
+impl Backend for TestBackend {
+    fn move_to(&mut self, x: u16, y: u16) -> Result<(), Error> {
+        self.log(MoveTo(x, y));
+        self.cursor = (x, y);
+        Ok(())
+    }
+
+    fn set_fg(&mut self, color: Color) -> Result<(), Error> {
+        self.log(SetForegroundColor(color));
+        self.style.foreground_color = Some(color).filter(|c| *c != Color::Reset);
+        Ok(())
+    }
+
+    fn set_bg(&mut self, color: Color) -> Result<(), Error> {
+        self.log(SetBackgroundColor(color));
+        self.style.background_color = Some(color).filter(|c| *c != Color::Reset);
+        Ok(())
+    }
+
+    fn set_attrs(&mut self, attributes: Attributes) -> Result<(), Error> {
+        self.log(SetAttributes(attributes));
+        self.style.attributes.extend(attributes);
+        Ok(())
+    }
+
+    fn reset_attrs(&mut self) -> Result<(), Error> {
+        self.log(SetAttribute(Attribute::Reset));
+        self.style = ContentStyle::default();
+        Ok(())
+    }
+
+    fn reset_color(&mut self) -> Result<(), Error> {
+        self.log(ResetColor);
+        self.style = ContentStyle::default();
+        Ok(())
+    }
+
+    fn print(&mut self, s: &str) -> Result<(), Error> {
+        self.output.push_str(s);
+        // A grapheme cluster takes a single cell, which only holds its first
+        // character here.
+        for cluster in s.graphemes(true) {
+            let mut chars = cluster.chars();
+            let c = chars.next().unwrap_or(' ');
+            if c == '\n' {
+                self.new_line();
+                continue;
+            }
+            let width = match chars.next() {
+                None => c.width().unwrap_or(1),
+                Some(_) => cluster.width(),
+            } as u16;
+            if width == 0 {
+                continue;
+            }
+            let (x, y) = self.cursor;
+            if x + width > self.width || y >= self.height {
+                continue;
+            }
+            let row = &mut self.cells[y as usize];
+            row[x as usize] = Some((c, self.style));
+            for cell in &mut row[x as usize + 1..(x + width) as usize] {
+                *cell = None;
+            }
+            self.cursor.0 += width;
+            if self.auto_wrap && self.cursor.0 >= self.width {
+                self.new_line();
+            }
+        }
+        Ok(())
+    }
+
+    fn show_cursor(&mut self) -> Result<(), Error> {
+        self.log(cursor::Show);
+        self.cursor_visible = true;
+        Ok(())
+    }
+
+    fn hide_cursor(&mut self) -> Result<(), Error> {
+        self.log(cursor::Hide);
+        self.cursor_visible = false;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn command(&mut self, command: impl Command) -> Result<(), Error> {
+        let mut ansi = String::new();
+        let _ = command.write_ansi(&mut ansi);
+        self.output.push_str(&ansi);
+        match ansi.as_str() {
+            "\x1b[?7l" => self.auto_wrap = false,
+            "\x1b[?7h" => self.auto_wrap = true,
+            _ => self.erase(&ansi),
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Result<(u16, u16), Error> {
+        Ok((self.width, self.height))
+    }
+
+    fn cursor_position(&self) -> Result<(u16, u16), Error> {
+        Ok(self.cursor)
+    }
+
+    fn enable_raw_mode(&mut self) -> Result<(), Error> {
+        self.raw_mode = true;
+        Ok(())
+    }
+
+    fn disable_raw_mode(&mut self) -> Result<(), Error> {
+        self.raw_mode = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Renderer;
+
+    #[test]
+    fn write_backend_emits_escapes() {
+        let mut output: Vec<u8> = vec![];
+        output.move_to(2, 1).unwrap();
+        output.set_fg(Color::Red).unwrap();
+        output.print("x").unwrap();
+        output.hide_cursor().unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\x1b[2;3H\x1b[38;5;9mx\x1b[?25l");
+    }
+
+    #[test]
+    fn renderer_draws_to_any_write() {
+        let mut renderer = Renderer::default();
+        renderer.probe_size(&TestBackend::new(4, 1)).unwrap();
+        renderer.begin().unwrap();
+        renderer.draw_str(0, 0, "ab", ContentStyle::default());
+
+        let mut output: Vec<u8> = vec![];
+        renderer.end(&mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("\x1b[1;1Hab"));
+    }
+
+    #[test]
+    fn test_backend_captures_screen() {
+        let mut tty = TestBackend::new(5, 2);
+        let mut renderer = Renderer::default();
+        renderer.term_on(&mut tty).unwrap();
+        renderer.begin().unwrap();
+        renderer.draw_str(0, 0, "hi", ContentStyle::default());
+        renderer.draw_str(1, 1, "日x", ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+
+        assert!(tty.to_lines()[0].starts_with("hi"));
+        assert_eq!(tty.to_lines(), ["hi   ", " 日x "]);
+        assert_eq!(tty.get_string(1, 1, 2), "日");
+        assert_eq!(tty.get_string(2, 1, 2), "x");
+        assert_eq!(tty.cell(2, 1), None);
+    }
+
+    #[test]
+    fn reset_color_clears_attributes() {
+        let mut tty = TestBackend::new(3, 1);
+        let mut style = ContentStyle::default();
+        style.attributes.set(Attribute::Bold);
+        tty.set_attrs(style.attributes).unwrap();
+        tty.set_fg(Color::Red).unwrap();
+        tty.print("a").unwrap();
+        tty.reset_color().unwrap();
+        tty.print("b").unwrap();
+
+        let bold_red = ContentStyle {
+            foreground_color: Some(Color::Red),
+            ..style
+        };
+        assert_eq!(tty.cell(0, 0), Some(('a', bold_red)));
+        assert_eq!(tty.cell(1, 0), Some(('b', ContentStyle::default())));
+
+        let mut ansi = String::new();
+        ResetColor.write_ansi(&mut ansi).unwrap();
+        assert_eq!(ansi, "\x1b[0m");
+    }
+
+    struct CountingWriter<'a> {
+        writes: &'a std::cell::Cell<usize>,
+    }
+
+    impl Write for CountingWriter<'_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writes.set(self.writes.get() + 1);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn buffered_frame_is_a_single_write() {
+        let writes = std::cell::Cell::new(0);
+        let mut tty =
+            std::io::BufWriter::with_capacity(0x10000, CountingWriter { writes: &writes });
+        let mut renderer = Renderer::default();
+        renderer.probe_size(&TestBackend::new(20, 5)).unwrap();
+        renderer.begin().unwrap();
+        for y in 0..5 {
+            renderer.draw_str(
+                0,
+                y,
+                "some text",
+                ContentStyle {
+                    foreground_color: Some(Color::Red),
+                    ..Default::default()
+                },
+            );
+            renderer.draw_str(10, y, "more", ContentStyle::default());
+        }
+        renderer.end(&mut tty).unwrap();
+        assert_eq!(writes.get(), 1);
+    }
+}
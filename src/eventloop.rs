@@ -0,0 +1,116 @@
+//! Stream of terminal events merged with periodic ticks
+
+use super::renderer::{Error, Renderer};
+use crossterm::event::{Event, EventStream};
+use futures::{future::poll_fn, FutureExt, Stream, StreamExt};
+use futures_timer::Delay;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum LoopEvent {
+    /// The interval elapsed, e.g. to advance animations
+    Tick,
+    Event(Event),
+}
+
+/// Waits for whichever comes first of a terminal event and the next tick,
+/// replacing a hand-written `select!` over `EventStream` and a `Delay`:
+///
+/// ```no_run
+/// # async fn run(renderer: &mut masof::Renderer) {
+/// # use std::time::Duration;
+/// let mut events = masof::eventloop::EventLoop::new(Duration::from_millis(100));
+/// while let Some(Ok(_event)) = events.next(renderer).await {
+///     // Handle the event, then redraw.
+/// }
+/// # }
+/// ```
+pub struct EventLoop<S = EventStream> {
+    events: S,
+    interval: Duration,
+    delay: Delay,
+}
+
+impl EventLoop {
+    /// Read events from the terminal, ticking every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self::with_stream(EventStream::new(), interval)
+    }
+}
+
+impl<S> EventLoop<S>
+where
+    S: Stream<Item = std::io::Result<Event>> + Unpin,
+{
+    /// Read events from any stream, e.g. a scripted one in tests.
+    pub fn with_stream(events: S, interval: Duration) -> Self {
+        Self {
+            events,
+            interval,
+            delay: Delay::new(interval),
+        }
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self.delay.reset(Instant::now() + interval);
+        self
+    }
+
+    /// The next event or tick, `None` once the event stream ends. Resize
+    /// events are passed to `renderer` before being returned.
+    pub async fn next(&mut self, renderer: &mut Renderer) -> Option<Result<LoopEvent, Error>> {
+        let item = poll_fn(|cx| {
+            if let Poll::Ready(item) = self.events.poll_next_unpin(cx) {
+                return Poll::Ready(item.map(|event| event.map(LoopEvent::Event)));
+            }
+            if self.delay.poll_unpin(cx).is_ready() {
+                self.delay.reset(Instant::now() + self.interval);
+                return Poll::Ready(Some(Ok(LoopEvent::Tick)));
+            }
+            Poll::Pending
+        })
+        .await;
+
+        if let Some(Ok(LoopEvent::Event(event))) = &item {
+            renderer.event(event);
+        }
+        item.map(|result| result.map_err(Error::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, stream};
+
+    #[test]
+    fn events_are_forwarded_to_renderer() {
+        let events = stream::iter(vec![Ok(Event::Resize(20, 5)), Ok(Event::FocusGained)]);
+        let mut events = EventLoop::with_stream(events, Duration::from_secs(60));
+        let mut renderer = Renderer::default();
+
+        block_on(async {
+            let first = events.next(&mut renderer).await;
+            assert!(matches!(first, Some(Ok(LoopEvent::Event(Event::Resize(20, 5))))));
+            assert_eq!((renderer.width(), renderer.height()), (20, 5));
+            let second = events.next(&mut renderer).await;
+            assert!(matches!(second, Some(Ok(LoopEvent::Event(Event::FocusGained)))));
+            assert!(events.next(&mut renderer).await.is_none());
+        });
+    }
+
+    #[test]
+    fn ticks_without_events() {
+        let mut events = EventLoop::with_stream(stream::pending(), Duration::from_millis(5));
+        let mut renderer = Renderer::default();
+
+        block_on(async {
+            for _ in 0..2 {
+                let tick = events.next(&mut renderer).await;
+                assert!(matches!(tick, Some(Ok(LoopEvent::Tick))));
+            }
+        });
+    }
+}
@@ -0,0 +1,174 @@
+//! Horizontal meter widget with end labels, tick marks and a value readout
+
+use super::{ContentStyle, Renderer};
+use unicode_width::UnicodeWidthStr;
+
+pub struct Gauge {
+    min: f64,
+    max: f64,
+    value: f64,
+    /// Number of intervals the tick marks divide the meter into
+    ticks: u16,
+    /// Digits after the decimal point in the labels and readout
+    precision: usize,
+}
+
+pub struct StyleMap {
+    pub filled: ContentStyle,
+    pub empty: ContentStyle,
+    pub label: ContentStyle,
+    /// Used for the arrow shown when the value is out of range
+    pub overflow: ContentStyle,
+}
+
+lazy_static::lazy_static! {
+    static ref DEF_STYLE_MAP : StyleMap = {
+        StyleMap {
+            filled: Default::default(),
+            empty: Default::default(),
+            label: Default::default(),
+            overflow: Default::default(),
+        }
+    };
+}
+
+impl Gauge {
+    pub fn def_style_map() -> &'static StyleMap {
+        &DEF_STYLE_MAP
+    }
+
+    pub fn new(min: f64, max: f64, value: f64) -> Self {
+        Self {
+            min,
+            max,
+            value,
+            ticks: 4,
+            precision: 1,
+        }
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn set_value(&mut self, value: f64) {
+        self.value = value;
+    }
+
+    pub fn set_ticks(&mut self, ticks: u16) {
+        self.ticks = ticks;
+    }
+
+    pub fn set_precision(&mut self, precision: usize) {
+        self.precision = precision;
+    }
+
+    /// Position of the value between `min` and `max`, clamped to `[0, 1]`
+    pub fn fraction(&self) -> f64 {
+        if self.max.is_nan() || self.min.is_nan() || self.max <= self.min || self.value.is_nan() {
+            return 0.0;
+        }
+        let fraction = (self.value - self.min) / (self.max - self.min);
+        fraction.clamp(0.0, 1.0)
+    }
+
+    pub fn draw(&self, x: u16, y: u16, width: u16, renderer: &mut Renderer, style_map: &StyleMap) {
+        let precision = self.precision;
+        let min_label = format!("{:.*} ", precision, self.min);
+        let max_label = format!(" {:.*}", precision, self.max);
+        let readout = format!(" {:.*}", precision, self.value);
+        let labels_width = (min_label.width() + max_label.width() + readout.width()) as u16;
+
+        let mut x = x;
+        let bar_width = if width >= labels_width + 2 {
+            x += renderer.draw_str(x, y, &min_label, style_map.label);
+            width - labels_width
+        } else {
+            width
+        };
+
+        let filled = (self.fraction() * bar_width as f64).round() as u16;
+        let ticks: Vec<u16> = if self.ticks > 1 {
+            (1..self.ticks)
+                .map(|k| (k as u32 * bar_width as u32 / self.ticks as u32) as u16)
+                .collect()
+        } else {
+            vec![]
+        };
+
+        for i in 0..bar_width {
+            let (c, style) = if i + 1 == bar_width && self.value > self.max {
+                ('▶', style_map.overflow)
+            } else if i == 0 && self.value < self.min {
+                ('◀', style_map.overflow)
+            } else if i < filled {
+                ('█', style_map.filled)
+            } else if ticks.contains(&i) {
+                ('┼', style_map.empty)
+            } else {
+                ('─', style_map.empty)
+            };
+            renderer.draw_char(x + i, y, c, style);
+        }
+        x += bar_width;
+
+        if bar_width < width {
+            x += renderer.draw_str(x, y, &max_label, style_map.label);
+            renderer.draw_str(x, y, &readout, style_map.label);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestBackend;
+
+    fn drawn(gauge: &Gauge, width: u16) -> String {
+        let mut renderer = Renderer::default();
+        renderer.probe_size(&TestBackend::new(width, 1)).unwrap();
+        renderer.begin().unwrap();
+        gauge.draw(0, 0, width, &mut renderer, Gauge::def_style_map());
+        renderer.render_to_text().trim_end().to_owned()
+    }
+
+    #[test]
+    fn fill_is_proportional() {
+        let mut gauge = Gauge::new(0.0, 10.0, 3.0);
+        gauge.set_ticks(1);
+        assert_eq!(drawn(&gauge, 23), "0.0 ███─────── 10.0 3.0");
+    }
+
+    #[test]
+    fn out_of_range_values_clamp() {
+        let mut gauge = Gauge::new(0.0, 10.0, 12.0);
+        gauge.set_ticks(1);
+        assert_eq!(gauge.fraction(), 1.0);
+        assert_eq!(drawn(&gauge, 8), "███████▶");
+
+        gauge.set_value(-1.0);
+        assert_eq!(gauge.fraction(), 0.0);
+        assert_eq!(drawn(&gauge, 8), "◀───────");
+    }
+
+    #[test]
+    fn ticks_divide_the_bar() {
+        let gauge = Gauge::new(0.0, 1.0, 0.0);
+        assert_eq!(drawn(&gauge, 8), "──┼─┼─┼─");
+    }
+
+    #[test]
+    fn many_ticks_do_not_overflow() {
+        let mut gauge = Gauge::new(0.0, 1.0, 0.0);
+        gauge.set_ticks(u16::MAX);
+        assert_eq!(drawn(&gauge, 1000).chars().count(), 1000);
+    }
+
+    #[test]
+    fn readout_is_rounded() {
+        let mut gauge = Gauge::new(0.0, 1.0, 0.1 + 0.2);
+        assert!(drawn(&gauge, 40).ends_with(" 1.0 0.3"));
+        gauge.set_precision(2);
+        assert!(drawn(&gauge, 40).ends_with(" 1.00 0.30"));
+    }
+}
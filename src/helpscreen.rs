@@ -0,0 +1,213 @@
+//! Key binding reference laid out from one or more `KeyMap`s
+
+use super::{keyaction::KeyMap, renderer::Align, ContentStyle, Renderer};
+use unicode_width::UnicodeWidthStr;
+
+/// Gap between the key and description columns
+const COLUMN_GAP: u16 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HelpLine {
+    Title(String),
+    /// Keys and the description of their action
+    Entry(String, String),
+    Blank,
+}
+
+#[derive(Default)]
+pub struct HelpScreen {
+    lines: Vec<HelpLine>,
+    page: usize,
+}
+
+pub struct StyleMap {
+    pub title: ContentStyle,
+    pub key: ContentStyle,
+    pub description: ContentStyle,
+    /// Used for the page indicator
+    pub page: ContentStyle,
+}
+
+lazy_static::lazy_static! {
+    static ref DEF_STYLE_MAP : StyleMap = {
+        use crossterm::style::Stylize;
+        StyleMap {
+            title: ContentStyle::new().bold(),
+            key: ContentStyle::new().cyan(),
+            description: Default::default(),
+            page: ContentStyle::new().dim(),
+        }
+    };
+}
+
+impl HelpScreen {
+    pub fn def_style_map() -> &'static StyleMap {
+        &DEF_STYLE_MAP
+    }
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the bindings of `map` under `title`. An empty title adds no
+    /// heading.
+    pub fn add_section<A>(&mut self, title: &str, map: &KeyMap<A>) -> &mut Self
+    where
+        A: std::fmt::Display + Ord,
+    {
+        if !self.lines.is_empty() {
+            self.lines.push(HelpLine::Blank);
+        }
+        if !title.is_empty() {
+            self.lines.push(HelpLine::Title(title.to_string()));
+        }
+        for (keys, action) in map.describe_entries() {
+            self.lines.push(HelpLine::Entry(keys, action.to_string()));
+        }
+        self
+    }
+
+    /// Every row of the layout, across all pages
+    pub fn lines(&self) -> &[HelpLine] {
+        &self.lines
+    }
+
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    /// Number of pages needed to show everything in `height` rows
+    pub fn page_count(&self, height: u16) -> usize {
+        let per_page = self.rows_per_page(height);
+        if per_page == 0 {
+            return 0;
+        }
+        std::cmp::max(self.lines.len().div_ceil(per_page), 1)
+    }
+
+    pub fn set_page(&mut self, page: usize) {
+        self.page = page;
+    }
+
+    pub fn next_page(&mut self) {
+        self.page += 1;
+    }
+
+    pub fn prev_page(&mut self) {
+        self.page = self.page.saturating_sub(1);
+    }
+
+    /// Reserve a row for the page indicator when everything doesn't fit.
+    fn rows_per_page(&self, height: u16) -> usize {
+        if self.lines.len() > height as usize {
+            height.saturating_sub(1) as usize
+        } else {
+            height as usize
+        }
+    }
+
+    /// Draw the current page within the rectangle, clamping the page to the
+    /// last one. Keys take the left column, as wide as the widest of them but
+    /// at most half of `width`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &mut self,
+        renderer: &mut Renderer,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        style_map: &StyleMap,
+    ) {
+        let page_count = self.page_count(height);
+        if page_count == 0 {
+            return;
+        }
+        self.page = self.page.min(page_count - 1);
+
+        let key_width = self
+            .lines
+            .iter()
+            .map(|line| match line {
+                HelpLine::Entry(keys, _) => keys.width() as u16,
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+            .min(width / 2);
+        let description_x = x.saturating_add(key_width + COLUMN_GAP);
+        let description_width = width.saturating_sub(key_width + COLUMN_GAP);
+
+        let per_page = self.rows_per_page(height);
+        let page = self.lines.iter().skip(self.page * per_page).take(per_page);
+        for (line, row) in page.zip(y..) {
+            match line {
+                HelpLine::Title(title) => {
+                    renderer.draw_truncated(x, row, width, title, style_map.title);
+                }
+                HelpLine::Entry(keys, description) => {
+                    renderer.draw_truncated(x, row, key_width, keys, style_map.key);
+                    renderer.draw_truncated(
+                        description_x,
+                        row,
+                        description_width,
+                        description,
+                        style_map.description,
+                    );
+                }
+                HelpLine::Blank => {}
+            }
+        }
+
+        if page_count > 1 {
+            let indicator = format!("{}/{}", self.page + 1, page_count);
+            let row = y.saturating_add(height - 1);
+            renderer.draw_aligned(x, row, width, &indicator, Align::Right, style_map.page);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestBackend;
+    use crossterm::event::KeyCode;
+
+    fn drawn(help: &mut HelpScreen, width: u16, height: u16) -> String {
+        let mut renderer = Renderer::default();
+        renderer.probe_size(&TestBackend::new(width, height)).unwrap();
+        renderer.begin().unwrap();
+        help.draw(&mut renderer, 0, 0, width, height, HelpScreen::def_style_map());
+        renderer.render_to_text()
+    }
+
+    #[test]
+    fn three_bindings_take_three_rows() {
+        let mut map = KeyMap::new();
+        map.add_ctrl(KeyCode::Char('q'), "quit");
+        map.add_ctrl(KeyCode::Char('s'), "save");
+        map.add_no_mods(KeyCode::F(1), "help");
+        let mut help = HelpScreen::new();
+        help.add_section("", &map);
+
+        assert_eq!(help.lines().len(), 3);
+        assert_eq!(help.page_count(5), 1);
+        assert_eq!(drawn(&mut help, 20, 5), "F1   help\nC-q  quit\nC-s  save\n\n\n");
+    }
+
+    #[test]
+    fn paginates_with_indicator() {
+        let mut map = KeyMap::new();
+        map.add_ctrl(KeyCode::Char('q'), "quit");
+        map.add_ctrl(KeyCode::Char('s'), "save");
+        let mut help = HelpScreen::new();
+        help.add_section("File", &map);
+
+        assert_eq!(help.page_count(2), 3);
+        help.set_page(1);
+        assert_eq!(drawn(&mut help, 12, 2), "C-q  quit\n         2/3\n");
+        help.set_page(9);
+        assert_eq!(drawn(&mut help, 12, 2), "C-s  save\n         3/3\n");
+        assert_eq!(help.page(), 2);
+    }
+}
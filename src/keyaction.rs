@@ -1,10 +1,15 @@
 //! Types to manage mapping of key combinations to actions
 
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::event::{
+    KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MediaKeyCode, ModifierKeyCode,
+};
 use std::collections::HashMap;
 use std::fmt::Write;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 
-#[derive(Debug, Hash, Copy, Clone, Default, Eq, PartialEq)]
+#[derive(Debug, Hash, Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Modifiers {
     ctrl: bool,
     alt: bool,
@@ -12,39 +17,146 @@ pub struct Modifiers {
 }
 
 impl Modifiers {
-    fn shift(self) -> Self {
+    pub fn shift(self) -> Self {
         Self {
             shift: true,
             ..self
         }
     }
 
-    fn ctrl(self) -> Self {
+    pub fn ctrl(self) -> Self {
         Self { ctrl: true, ..self }
     }
+
+    pub fn alt(self) -> Self {
+        Self { alt: true, ..self }
+    }
+
+    /// If `s` starts with a modifier prefix, its length and these modifiers
+    /// with it added
+    fn strip_prefix(self, s: &str) -> Option<(usize, Self)> {
+        if s.starts_with("C-") {
+            Some((2, self.ctrl()))
+        } else if s.starts_with("M-") {
+            Some((2, self.alt()))
+        } else if s.starts_with("Shift-") {
+            Some((6, self.shift()))
+        } else if s.starts_with("S-") {
+            Some((2, self.shift()))
+        } else {
+            None
+        }
+    }
+}
+
+/// The prefixes of a key combination, e.g. `C-Shift-`, and nothing without
+/// modifiers.
+impl fmt::Display for Modifiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "C-")?;
+        }
+        if self.alt {
+            write!(f, "M-")?;
+        }
+        if self.shift {
+            write!(f, "Shift-")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the format produced by `Display`, accepting `S-` for `Shift-` as
+/// key combinations do.
+impl FromStr for Modifiers {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = Modifiers::default();
+        let mut rest = s;
+        while !rest.is_empty() {
+            match modifiers.strip_prefix(rest) {
+                Some((prefix_len, m)) => {
+                    modifiers = m;
+                    rest = &rest[prefix_len..];
+                }
+                None => return Err(ParseKeyError::UnknownModifier(rest.to_owned())),
+            }
+        }
+        Ok(modifiers)
+    }
 }
 
-#[derive(Eq, Hash, PartialEq, Debug)]
+/// Serialized as the string form of `Display`, e.g. `"C-M-"`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Modifiers {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Modifiers {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl From<KeyModifiers> for Modifiers {
+    fn from(modifiers: KeyModifiers) -> Self {
+        Self {
+            ctrl: modifiers.contains(KeyModifiers::CONTROL),
+            shift: modifiers.contains(KeyModifiers::SHIFT),
+            alt: modifiers.contains(KeyModifiers::ALT),
+        }
+    }
+}
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
 pub enum KeyCombination {
     Specific(KeyCode, Modifiers),
     AllChars(Modifiers),
 }
 
+use std::cmp::Ordering;
 use std::fmt;
 
+/// Orders specific keys by key code, then modifiers, followed by the
+/// catch-all combinations.
+impl Ord for KeyCombination {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (KeyCombination::Specific(a, a_mods), KeyCombination::Specific(b, b_mods)) => a
+                .partial_cmp(b)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a_mods.cmp(b_mods)),
+            (KeyCombination::Specific(..), KeyCombination::AllChars(_)) => Ordering::Less,
+            (KeyCombination::AllChars(_), KeyCombination::Specific(..)) => Ordering::Greater,
+            (KeyCombination::AllChars(a_mods), KeyCombination::AllChars(b_mods)) => {
+                a_mods.cmp(b_mods)
+            }
+        }
+    }
+}
+
+impl PartialOrd for KeyCombination {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl fmt::Display for KeyCombination {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modifiers = match self {
+            KeyCombination::Specific(_, modifiers) | KeyCombination::AllChars(modifiers) => {
+                modifiers
+            }
+        };
+        write!(f, "{}", modifiers)?;
+
         match self {
-            KeyCombination::Specific(key_code, modifiers) => {
-                if modifiers.ctrl {
-                    write!(f, "C-")?;
-                }
-                if modifiers.alt {
-                    write!(f, "M-")?;
-                }
-                if modifiers.shift {
-                    write!(f, "Shift-")?;
-                }
+            KeyCombination::Specific(key_code, _) => {
                 let s = match key_code {
                     KeyCode::Backspace => "Backspace".to_owned(),
                     KeyCode::Enter => "Enter".to_owned(),
@@ -61,31 +173,205 @@ impl fmt::Display for KeyCombination {
                     KeyCode::Delete => "Delete".to_owned(),
                     KeyCode::Insert => "Insert".to_owned(),
                     KeyCode::F(i) => format!("F{}", i),
-                    KeyCode::Char(' ') => format!("Space"),
-                    KeyCode::Char('*') => format!("'*'"),
-                    KeyCode::Char(',') => format!("','"),
+                    KeyCode::Char(' ') => "Space".to_owned(),
+                    KeyCode::Char('*') => "'*'".to_owned(),
+                    KeyCode::Char(',') => "','".to_owned(),
                     KeyCode::Char(ch) => format!("{}", ch),
-                    KeyCode::Null => format!("<null>"),
-                    KeyCode::Esc => format!("Esc"),
-                    KeyCode::CapsLock => format!("CapsLock"),
-                    KeyCode::ScrollLock => format!("ScrollLock"),
-                    KeyCode::NumLock => format!("NumLock"),
-                    KeyCode::PrintScreen => format!("PrintScreen"),
-                    KeyCode::Pause => format!("Pause"),
-                    KeyCode::Menu => format!("Menu"),
-                    KeyCode::KeypadBegin => format!("KeypadBegin"),
-                    KeyCode::Media(_) => format!("Media<?>"),
-                    KeyCode::Modifier(_) => format!("Modifier<?>"),
+                    KeyCode::Null => "<null>".to_owned(),
+                    KeyCode::Esc => "Esc".to_owned(),
+                    KeyCode::CapsLock => "CapsLock".to_owned(),
+                    KeyCode::ScrollLock => "ScrollLock".to_owned(),
+                    KeyCode::NumLock => "NumLock".to_owned(),
+                    KeyCode::PrintScreen => "PrintScreen".to_owned(),
+                    KeyCode::Pause => "Pause".to_owned(),
+                    KeyCode::Menu => "Menu".to_owned(),
+                    KeyCode::KeypadBegin => "KeypadBegin".to_owned(),
+                    KeyCode::Media(key) => format!("Media<{:?}>", key),
+                    KeyCode::Modifier(key) => format!("Modifier<{:?}>", key),
                 };
                 write!(f, "{}", s)
             }
-            _ => write!(f, "?"),
+            KeyCombination::AllChars(_) => write!(f, "<char>"),
         }
     }
 }
 
+const MEDIA_KEYS: [MediaKeyCode; 13] = [
+    MediaKeyCode::Play,
+    MediaKeyCode::Pause,
+    MediaKeyCode::PlayPause,
+    MediaKeyCode::Reverse,
+    MediaKeyCode::Stop,
+    MediaKeyCode::FastForward,
+    MediaKeyCode::Rewind,
+    MediaKeyCode::TrackNext,
+    MediaKeyCode::TrackPrevious,
+    MediaKeyCode::Record,
+    MediaKeyCode::LowerVolume,
+    MediaKeyCode::RaiseVolume,
+    MediaKeyCode::MuteVolume,
+];
+
+const MODIFIER_KEYS: [ModifierKeyCode; 14] = [
+    ModifierKeyCode::LeftShift,
+    ModifierKeyCode::LeftControl,
+    ModifierKeyCode::LeftAlt,
+    ModifierKeyCode::LeftSuper,
+    ModifierKeyCode::LeftHyper,
+    ModifierKeyCode::LeftMeta,
+    ModifierKeyCode::RightShift,
+    ModifierKeyCode::RightControl,
+    ModifierKeyCode::RightAlt,
+    ModifierKeyCode::RightSuper,
+    ModifierKeyCode::RightHyper,
+    ModifierKeyCode::RightMeta,
+    ModifierKeyCode::IsoLevel3Shift,
+    ModifierKeyCode::IsoLevel5Shift,
+];
+
+/// The key of `keys` whose name is given between `<>` after `prefix`, as in
+/// `Media<Play>`
+fn parse_named<K: fmt::Debug + Copy>(s: &str, prefix: &str, keys: &[K]) -> Option<K> {
+    let name = s.strip_prefix(prefix)?.strip_prefix('<')?.strip_suffix('>')?;
+    keys.iter().copied().find(|key| format!("{:?}", key) == name)
+}
+
+/// Terminals report shifted characters in upper case
+fn shifted(code: KeyCode) -> KeyCode {
+    match code {
+        KeyCode::Char(c) => KeyCode::Char(c.to_uppercase().next().unwrap_or(c)),
+        x => x,
+    }
+}
+
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum ParseKeyError {
+    #[error("Empty key combination")]
+    Empty,
+
+    #[error("Unknown key name; {0}")]
+    UnknownKey(String),
+
+    #[error("Unknown modifier; {0}")]
+    UnknownModifier(String),
+}
+
+/// Parses the format produced by `Display`, e.g. `C-M-Shift-F5`, `Enter`,
+/// `Space`, `Media<Play>` or `x`. `S-` is accepted as a short form of
+/// `Shift-`.
+impl FromStr for KeyCombination {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ParseKeyError::Empty);
+        }
+
+        let mut modifiers = Modifiers::default();
+        let mut rest = s;
+        while let Some((prefix_len, m)) = modifiers.strip_prefix(rest) {
+            // A trailing '-' is the key itself, as in "C--"
+            if rest.len() == prefix_len {
+                break;
+            }
+            modifiers = m;
+            rest = &rest[prefix_len..];
+        }
+
+        let key_code = match rest {
+            "<char>" => return Ok(KeyCombination::AllChars(modifiers)),
+            "Backspace" => KeyCode::Backspace,
+            "Enter" => KeyCode::Enter,
+            "Left" => KeyCode::Left,
+            "Right" => KeyCode::Right,
+            "Up" => KeyCode::Up,
+            "Down" => KeyCode::Down,
+            "Home" => KeyCode::Home,
+            "End" => KeyCode::End,
+            "PageUp" => KeyCode::PageUp,
+            "PageDown" => KeyCode::PageDown,
+            "Tab" => KeyCode::Tab,
+            "BackTab" => KeyCode::BackTab,
+            "Delete" => KeyCode::Delete,
+            "Insert" => KeyCode::Insert,
+            "Space" => KeyCode::Char(' '),
+            "<null>" => KeyCode::Null,
+            "Esc" => KeyCode::Esc,
+            "CapsLock" => KeyCode::CapsLock,
+            "ScrollLock" => KeyCode::ScrollLock,
+            "NumLock" => KeyCode::NumLock,
+            "PrintScreen" => KeyCode::PrintScreen,
+            "Pause" => KeyCode::Pause,
+            "Menu" => KeyCode::Menu,
+            "KeypadBegin" => KeyCode::KeypadBegin,
+            _ => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next(), chars.next(), chars.next()) {
+                    (Some(ch), None, _, _) => KeyCode::Char(ch),
+                    (Some('\''), Some(ch), Some('\''), None) => KeyCode::Char(ch),
+                    _ => {
+                        if let Some(Ok(i)) = rest.strip_prefix('F').map(|n| n.parse()) {
+                            KeyCode::F(i)
+                        } else if let Some(key) = parse_named(rest, "Media", &MEDIA_KEYS) {
+                            KeyCode::Media(key)
+                        } else if let Some(key) = parse_named(rest, "Modifier", &MODIFIER_KEYS) {
+                            KeyCode::Modifier(key)
+                        } else {
+                            return Err(ParseKeyError::UnknownKey(rest.to_owned()));
+                        }
+                    }
+                }
+            }
+        };
+
+        Ok(KeyCombination::Specific(key_code, modifiers))
+    }
+}
+
+/// Serialized as the string form of `Display`, e.g. `"C-x"`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for KeyCombination {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for KeyCombination {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// What `KeyMap::merge` does with a key bound in both maps
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConflictPolicy {
+    KeepExisting,
+    Overwrite,
+    /// Fail without changing the map
+    Error,
+}
+
+/// How the fallback action of a `KeyMap` is keyed in descriptions and
+/// dumped bindings
+const ANY_KEY: &str = "<any>";
+
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum MergeError {
+    #[error("Key combination bound in both maps; {0}")]
+    Conflict(KeyCombination),
+
+    #[error("Both maps have a fallback action")]
+    ConflictingFallback,
+}
+
+#[derive(Clone)]
 pub struct KeyMap<A> {
     map: HashMap<KeyCombination, A>,
+    /// Action for keys that match no binding. Boxed, as a `KeyTree` holds
+    /// maps of its own nodes.
+    fallback: Option<Box<A>>,
 }
 
 impl<A> Default for KeyMap<A> {
@@ -98,6 +384,7 @@ impl<A> KeyMap<A> {
     pub fn new() -> Self {
         Self {
             map: Default::default(),
+            fallback: None,
         }
     }
 
@@ -105,27 +392,149 @@ impl<A> KeyMap<A> {
         &self.map
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&KeyCombination, &A)> {
+        self.map.iter()
+    }
+
+    pub fn bindings_sorted(&self) -> Vec<(&KeyCombination, &A)> {
+        let mut bindings: Vec<_> = self.map.iter().collect();
+        bindings.sort_by(|a, b| a.0.cmp(b.0));
+        bindings
+    }
+
+    pub fn contains(&self, key: &KeyCombination) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Unbind a key combination, returning the action it was bound to.
+    pub fn remove(&mut self, key: &KeyCombination) -> Option<A> {
+        self.map.remove(key)
+    }
+
+    /// Bind a key combination, replacing and returning any existing action.
+    pub fn rebind(&mut self, key: KeyCombination, a: A) -> Option<A> {
+        let old = self.remove(&key);
+        self.map.insert(key, a);
+        old
+    }
+
+    /// Bind a key combination unless it is already bound, in which case the
+    /// action is handed back.
+    pub fn try_add(&mut self, key: KeyCombination, a: A) -> Result<(), A> {
+        if self.map.contains_key(&key) {
+            return Err(a);
+        }
+        self.map.insert(key, a);
+        Ok(())
+    }
+
+    /// Key combinations bound in both maps, sorted, e.g. to warn about user
+    /// bindings that would replace defaults.
+    pub fn conflicts_with<B>(&self, other: &KeyMap<B>) -> Vec<&KeyCombination> {
+        let mut conflicts: Vec<_> = self
+            .map
+            .keys()
+            .filter(|key| other.map.contains_key(key))
+            .collect();
+        conflicts.sort();
+        conflicts
+    }
+
+    /// Add the bindings of `other`, e.g. user overrides over the defaults,
+    /// resolving keys bound in both by `on_conflict`. The fallback action
+    /// counts as a binding too.
+    pub fn merge(
+        &mut self,
+        other: KeyMap<A>,
+        on_conflict: ConflictPolicy,
+    ) -> Result<(), MergeError> {
+        if on_conflict == ConflictPolicy::Error {
+            if let Some(key) = self.conflicts_with(&other).first() {
+                return Err(MergeError::Conflict(**key));
+            }
+            if self.fallback.is_some() && other.fallback.is_some() {
+                return Err(MergeError::ConflictingFallback);
+            }
+        }
+
+        let keep = on_conflict == ConflictPolicy::KeepExisting;
+        for (key, a) in other.map {
+            if !(keep && self.map.contains_key(&key)) {
+                self.map.insert(key, a);
+            }
+        }
+        if let Some(a) = other.fallback {
+            if !(keep && self.fallback.is_some()) {
+                self.fallback = Some(a);
+            }
+        }
+        Ok(())
+    }
+
+    /// Bindings keyed by the string form of their key combination, sorted,
+    /// for saving to a config file. The fallback action comes last, keyed
+    /// `<any>`.
+    pub fn dump_bindings(&self) -> Vec<(String, &A)> {
+        let mut bindings: Vec<_> = self
+            .bindings_sorted()
+            .into_iter()
+            .map(|(key, action)| (format!("{}", key), action))
+            .collect();
+        if let Some(action) = self.fallback.as_deref() {
+            bindings.push((ANY_KEY.to_string(), action));
+        }
+        bindings
+    }
+
+    /// Build a map from bindings keyed as produced by `dump_bindings`.
+    pub fn load_bindings<K>(
+        bindings: impl IntoIterator<Item = (K, A)>,
+    ) -> Result<Self, ParseKeyError>
+    where
+        K: AsRef<str>,
+    {
+        let mut map = HashMap::new();
+        let mut fallback = None;
+        for (key, action) in bindings {
+            if key.as_ref() == ANY_KEY {
+                fallback = Some(Box::new(action));
+            } else {
+                map.insert(key.as_ref().parse()?, action);
+            }
+        }
+        Ok(Self { map, fallback })
+    }
+
+    /// Bind a key with any combination of modifiers, e.g.
+    /// `Modifiers::default().ctrl().shift()`. With shift, characters are
+    /// bound in upper case, as terminals report them.
+    pub fn add(&mut self, code: KeyCode, modifiers: Modifiers, a: A) {
+        let code = if modifiers.shift { shifted(code) } else { code };
+        self.map.insert(KeyCombination::Specific(code, modifiers), a);
+    }
+
     pub fn add_no_mods(&mut self, code: KeyCode, a: A) {
-        self.map
-            .insert(KeyCombination::Specific(code, Modifiers::default()), a);
+        self.add(code, Modifiers::default(), a);
     }
 
     pub fn add_ctrl(&mut self, code: KeyCode, a: A) {
-        self.map.insert(
-            KeyCombination::Specific(code, Modifiers::default().ctrl()),
-            a,
-        );
+        self.add(code, Modifiers::default().ctrl(), a);
     }
 
     pub fn add_shift(&mut self, code: KeyCode, a: A) {
-        let code = match code {
-            KeyCode::Char(c) => KeyCode::Char(c.to_uppercase().to_string().chars().nth(0).unwrap()),
-            x => x,
-        };
-        self.map.insert(
-            KeyCombination::Specific(code, Modifiers::default().shift()),
-            a,
-        );
+        self.add(code, Modifiers::default().shift(), a);
+    }
+
+    pub fn add_alt(&mut self, code: KeyCode, a: A) {
+        self.add(code, Modifiers::default().alt(), a);
+    }
+
+    pub fn add_ctrl_alt(&mut self, code: KeyCode, a: A) {
+        self.add(code, Modifiers::default().ctrl().alt(), a);
+    }
+
+    pub fn add_ctrl_shift(&mut self, code: KeyCode, a: A) {
+        self.add(code, Modifiers::default().ctrl().shift(), a);
     }
 
     pub fn add_char_no_handler(&mut self, a: A) {
@@ -138,13 +547,28 @@ impl<A> KeyMap<A> {
             .insert(KeyCombination::AllChars(Modifiers::default().shift()), a);
     }
 
+    /// Bind every key that matches no other binding, e.g. to route unmapped
+    /// keys to a default handler.
+    pub fn add_any(&mut self, a: A) {
+        self.fallback = Some(Box::new(a));
+    }
+
+    pub fn fallback(&self) -> Option<&A> {
+        self.fallback.as_deref()
+    }
+
+    pub fn remove_fallback(&mut self) -> Option<A> {
+        self.fallback.take().map(|a| *a)
+    }
+
+    /// The action bound to a key event. Key releases, reported by terminals
+    /// with the enhanced keyboard protocol, match nothing, so that an action
+    /// doesn't fire twice for one keypress.
     pub fn get_action(&self, key_event: KeyEvent) -> Option<&A> {
-        let modifiers = key_event.modifiers;
-        let modifiers = Modifiers {
-            ctrl: modifiers.contains(KeyModifiers::CONTROL),
-            shift: modifiers.contains(KeyModifiers::SHIFT),
-            alt: modifiers.contains(KeyModifiers::ALT),
-        };
+        if key_event.kind == KeyEventKind::Release {
+            return None;
+        }
+        let modifiers = Modifiers::from(key_event.modifiers);
         if let Some(action) = self
             .map
             .get(&KeyCombination::Specific(key_event.code, modifiers))
@@ -156,11 +580,13 @@ impl<A> KeyMap<A> {
                 return Some(action);
             }
         }
-        None
+        self.fallback.as_deref()
     }
 
-    pub fn describe(&self, output: &mut String)
-        where A: std::fmt::Display + Ord
+    /// Each action paired with the keys bound to it, joined by " / ",
+    /// leaving the layout to the caller.
+    pub fn describe_entries(&self) -> Vec<(String, &A)>
+        where A: Ord
     {
         let mut action_to_keys = std::collections::BTreeMap::new();
         for (key, value) in self.map.iter() {
@@ -172,7 +598,9 @@ impl<A> KeyMap<A> {
             v.push(key);
         }
 
+        let mut entries = vec![];
         for (action, mut keys) in action_to_keys.into_iter() {
+            keys.sort();
             let mut str_keys = vec![];
             for key in keys.drain(..) {
                 match key {
@@ -180,22 +608,50 @@ impl<A> KeyMap<A> {
                         str_keys.push(format!("{}", key));
                     }
                     KeyCombination::AllChars { .. } => {
-                        if str_keys.len() == 0 {
-                            str_keys.push("<char>".to_string());
-                        } else {
+                        if str_keys.is_empty() {
+                            str_keys.push(format!("{}", key));
                         }
                     }
                 }
             }
+            entries.push((str_keys.join(" / "), action));
+        }
+        if let Some(action) = self.fallback.as_deref() {
+            entries.push((ANY_KEY.to_string(), action));
+        }
+        entries
+    }
+
+    pub fn describe(&self, output: &mut String)
+        where A: std::fmt::Display + Ord
+    {
+        for (keys, action) in self.describe_entries() {
             let _ = writeln!(
                 output,
                 "    {:width$}  - {}",
-                str_keys.join(" / "),
+                keys,
                 action,
                 width = 17
             );
         }
-        let _ = writeln!(output, "");
+        let _ = writeln!(output);
+    }
+}
+
+/// Serialized as a map from the string form of each key combination to its
+/// action, as given by `dump_bindings`.
+#[cfg(feature = "serde")]
+impl<A: serde::Serialize> serde::Serialize for KeyMap<A> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.dump_bindings())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, A: serde::Deserialize<'de>> serde::Deserialize<'de> for KeyMap<A> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bindings = HashMap::<String, A>::deserialize(deserializer)?;
+        Self::load_bindings(bindings).map_err(serde::de::Error::custom)
     }
 }
 
@@ -213,6 +669,15 @@ where
     }
 }
 
+/// Result of resolving key events against a `KeyTree`
+#[derive(Debug, Eq, PartialEq)]
+pub enum MatchResult<'a, A> {
+    /// The events are a prefix of at least one bound sequence
+    Pending,
+    Matched(&'a A),
+    NoMatch,
+}
+
 #[derive(Default)]
 pub struct KeyTree<A> {
     map: KeyMap<TreeNode<A>>,
@@ -227,7 +692,46 @@ impl<A> KeyTree<A> {
         &self.map
     }
 
-    pub fn add_vector(&mut self, _code: Vec<KeyCombination>, _a: A) {}
+    /// Bind a sequence of key combinations, such as `C-x C-c`, to an action.
+    /// Prefixes of the sequence become nested trees, replacing any action
+    /// that was bound to them.
+    pub fn add_vector(&mut self, code: Vec<KeyCombination>, a: A) {
+        let mut code = code.into_iter();
+        let first = match code.next() {
+            Some(first) => first,
+            None => return,
+        };
+        let rest: Vec<_> = code.collect();
+        if rest.is_empty() {
+            self.map.map.insert(first, TreeNode::Action(a));
+            return;
+        }
+
+        let node = self
+            .map
+            .map
+            .entry(first)
+            .or_insert_with(|| TreeNode::Tree(KeyTree::new()));
+        if let TreeNode::Action(_) = node {
+            *node = TreeNode::Tree(KeyTree::new());
+        }
+        if let TreeNode::Tree(tree) = node {
+            tree.add_vector(rest, a);
+        }
+    }
+
+    /// Resolve a sequence of key events against the tree.
+    pub fn get_action(&self, key_events: &[KeyEvent]) -> MatchResult<'_, A> {
+        let (first, rest) = match key_events.split_first() {
+            Some(x) => x,
+            None => return MatchResult::Pending,
+        };
+        match self.map.get_action(*first) {
+            Some(TreeNode::Tree(tree)) => tree.get_action(rest),
+            Some(TreeNode::Action(a)) if rest.is_empty() => MatchResult::Matched(a),
+            _ => MatchResult::NoMatch,
+        }
+    }
 
     pub fn add_no_mods(&mut self, code: KeyCode, a: A) {
         self.map.add_no_mods(code, TreeNode::Action(a))
@@ -249,3 +753,493 @@ impl<A> KeyTree<A> {
         self.map.add_char_shift(TreeNode::Action(a))
     }
 }
+
+/// Feeds key events one at a time through a `KeyTree`, remembering a
+/// partially entered sequence between calls.
+pub struct KeyTreeMatcher<'a, A> {
+    tree: &'a KeyTree<A>,
+    pending: Vec<KeyEvent>,
+    /// A pending prefix older than this is abandoned on the next event
+    timeout: Option<Duration>,
+    last_event: Option<Instant>,
+}
+
+impl<'a, A> KeyTreeMatcher<'a, A> {
+    pub fn new(tree: &'a KeyTree<A>) -> Self {
+        Self {
+            tree,
+            pending: vec![],
+            timeout: None,
+            last_event: None,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Advance by a key event. Key releases are ignored, leaving a partially
+    /// entered sequence pending.
+    pub fn feed(&mut self, key_event: KeyEvent) -> MatchResult<'a, A> {
+        if key_event.kind == KeyEventKind::Release {
+            return if self.is_pending() {
+                MatchResult::Pending
+            } else {
+                MatchResult::NoMatch
+            };
+        }
+
+        if let (Some(timeout), Some(last_event)) = (self.timeout, self.last_event) {
+            if last_event.elapsed() > timeout {
+                self.reset();
+            }
+        }
+
+        self.pending.push(key_event);
+        let result = self.tree.get_action(&self.pending);
+        match result {
+            MatchResult::Pending => self.last_event = Some(Instant::now()),
+            MatchResult::Matched(_) | MatchResult::NoMatch => self.reset(),
+        }
+        result
+    }
+
+    /// Abandon a partially entered sequence.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.last_event = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctrl(c: char) -> KeyCombination {
+        KeyCombination::Specific(KeyCode::Char(c), Modifiers::default().ctrl())
+    }
+
+    fn ctrl_key(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    enum Action {
+        Quit,
+        Save,
+    }
+
+    #[test]
+    fn key_tree_resolves_sequence() {
+        let mut tree = KeyTree::new();
+        tree.add_vector(vec![ctrl('x'), ctrl('c')], Action::Quit);
+        tree.add_vector(vec![ctrl('x'), ctrl('s')], Action::Save);
+
+        assert_eq!(tree.get_action(&[ctrl_key('x')]), MatchResult::Pending);
+        assert_eq!(
+            tree.get_action(&[ctrl_key('x'), ctrl_key('c')]),
+            MatchResult::Matched(&Action::Quit)
+        );
+        assert_eq!(
+            tree.get_action(&[ctrl_key('x'), ctrl_key('s')]),
+            MatchResult::Matched(&Action::Save)
+        );
+        assert_eq!(tree.get_action(&[ctrl_key('c')]), MatchResult::NoMatch);
+        assert_eq!(
+            tree.get_action(&[ctrl_key('x'), ctrl_key('c'), ctrl_key('c')]),
+            MatchResult::NoMatch
+        );
+    }
+
+    #[test]
+    fn matcher_feeds_chords() {
+        let mut tree = KeyTree::new();
+        tree.add_vector(vec![ctrl('x'), ctrl('c')], Action::Quit);
+        let mut matcher = KeyTreeMatcher::new(&tree);
+
+        assert_eq!(matcher.feed(ctrl_key('x')), MatchResult::Pending);
+        assert!(matcher.is_pending());
+        assert_eq!(matcher.feed(ctrl_key('c')), MatchResult::Matched(&Action::Quit));
+        assert!(!matcher.is_pending());
+
+        assert_eq!(matcher.feed(ctrl_key('x')), MatchResult::Pending);
+        let q = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE);
+        assert_eq!(matcher.feed(q), MatchResult::NoMatch);
+        assert!(!matcher.is_pending());
+    }
+
+    #[test]
+    fn matcher_reset_and_timeout() {
+        let mut tree = KeyTree::new();
+        tree.add_vector(vec![ctrl('x'), ctrl('c')], Action::Quit);
+        let mut matcher = KeyTreeMatcher::new(&tree);
+
+        matcher.feed(ctrl_key('x'));
+        matcher.reset();
+        assert_eq!(matcher.feed(ctrl_key('c')), MatchResult::NoMatch);
+
+        matcher.set_timeout(Some(Duration::ZERO));
+        matcher.feed(ctrl_key('x'));
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(matcher.feed(ctrl_key('c')), MatchResult::NoMatch);
+    }
+
+    #[test]
+    fn alt_bindings_resolve() {
+        let mut map = KeyMap::new();
+        map.add_alt(KeyCode::Char('f'), Action::Save);
+        map.add_ctrl_alt(KeyCode::Char('q'), Action::Quit);
+        map.add_ctrl_shift(KeyCode::Char('s'), Action::Save);
+
+        let alt_f = KeyEvent::new(KeyCode::Char('f'), KeyModifiers::ALT);
+        assert_eq!(map.get_action(alt_f), Some(&Action::Save));
+        let f = KeyEvent::new(KeyCode::Char('f'), KeyModifiers::NONE);
+        assert_eq!(map.get_action(f), None);
+        let ctrl_alt_q =
+            KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL | KeyModifiers::ALT);
+        assert_eq!(map.get_action(ctrl_alt_q), Some(&Action::Quit));
+        let ctrl_shift_s =
+            KeyEvent::new(KeyCode::Char('S'), KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+        assert_eq!(map.get_action(ctrl_shift_s), Some(&Action::Save));
+    }
+
+    #[test]
+    fn parse_modifier_prefixes() {
+        let parse = |s: &str| s.parse::<KeyCombination>().unwrap();
+        let x = KeyCode::Char('x');
+        assert_eq!(parse("C-x"), KeyCombination::Specific(x, Modifiers::default().ctrl()));
+        assert_eq!(parse("M-x"), KeyCombination::Specific(x, Modifiers::default().alt()));
+        assert_eq!(
+            parse("Shift-x"),
+            KeyCombination::Specific(x, Modifiers::default().shift())
+        );
+        assert_eq!(parse("S-x"), parse("Shift-x"));
+        assert_eq!(
+            parse("C-M-S-F5"),
+            KeyCombination::Specific(KeyCode::F(5), Modifiers::default().ctrl().alt().shift())
+        );
+        assert_eq!(
+            parse("C--"),
+            KeyCombination::Specific(KeyCode::Char('-'), Modifiers::default().ctrl())
+        );
+        assert_eq!(
+            parse("M-<char>"),
+            KeyCombination::AllChars(Modifiers::default().alt())
+        );
+    }
+
+    #[test]
+    fn parse_named_keys() {
+        let parse = |s: &str| s.parse::<KeyCombination>().unwrap();
+        let plain = |code| KeyCombination::Specific(code, Modifiers::default());
+        assert_eq!(parse("F1"), plain(KeyCode::F(1)));
+        assert_eq!(parse("F12"), plain(KeyCode::F(12)));
+        assert_eq!(parse("Enter"), plain(KeyCode::Enter));
+        assert_eq!(parse("Space"), plain(KeyCode::Char(' ')));
+        assert_eq!(parse("','"), plain(KeyCode::Char(',')));
+        assert_eq!(parse("Media<Play>"), plain(KeyCode::Media(MediaKeyCode::Play)));
+
+        assert_eq!("".parse::<KeyCombination>(), Err(ParseKeyError::Empty));
+        assert_eq!(
+            "C-Foo".parse::<KeyCombination>(),
+            Err(ParseKeyError::UnknownKey("Foo".to_owned()))
+        );
+        assert!("Fx".parse::<KeyCombination>().is_err());
+        assert!("Media<Nope>".parse::<KeyCombination>().is_err());
+    }
+
+    #[test]
+    fn parse_modifiers() {
+        let all = Modifiers::default().ctrl().alt().shift();
+        assert_eq!(all.to_string(), "C-M-Shift-");
+        assert_eq!("C-M-Shift-".parse::<Modifiers>(), Ok(all));
+        assert_eq!("S-".parse::<Modifiers>(), Ok(Modifiers::default().shift()));
+        assert_eq!("".parse::<Modifiers>(), Ok(Modifiers::default()));
+        assert_eq!(
+            "C-x".parse::<Modifiers>(),
+            Err(ParseKeyError::UnknownModifier("x".to_owned()))
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let mut codes = vec![
+            KeyCode::Backspace,
+            KeyCode::Enter,
+            KeyCode::Left,
+            KeyCode::Right,
+            KeyCode::Up,
+            KeyCode::Down,
+            KeyCode::Home,
+            KeyCode::End,
+            KeyCode::PageUp,
+            KeyCode::PageDown,
+            KeyCode::Tab,
+            KeyCode::BackTab,
+            KeyCode::Delete,
+            KeyCode::Insert,
+            KeyCode::F(1),
+            KeyCode::F(24),
+            KeyCode::Null,
+            KeyCode::Esc,
+            KeyCode::CapsLock,
+            KeyCode::ScrollLock,
+            KeyCode::NumLock,
+            KeyCode::PrintScreen,
+            KeyCode::Pause,
+            KeyCode::Menu,
+            KeyCode::KeypadBegin,
+        ];
+        codes.extend(" *,-'<Cx\u{e9}".chars().map(KeyCode::Char));
+        codes.extend(MEDIA_KEYS.iter().copied().map(KeyCode::Media));
+        codes.extend(MODIFIER_KEYS.iter().copied().map(KeyCode::Modifier));
+
+        for ctrl in [false, true] {
+            for alt in [false, true] {
+                for shift in [false, true] {
+                    let modifiers = Modifiers { ctrl, alt, shift };
+                    let mut keys = vec![KeyCombination::AllChars(modifiers)];
+                    keys.extend(
+                        codes
+                            .iter()
+                            .map(|code| KeyCombination::Specific(*code, modifiers)),
+                    );
+                    for key in keys {
+                        assert_eq!(format!("{}", key).parse(), Ok(key), "{}", key);
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn key_map_json_round_trip() {
+        #[derive(Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+        enum Command {
+            Quit,
+            Save,
+        }
+
+        let mut map = KeyMap::new();
+        map.add_ctrl(KeyCode::Char('q'), Command::Quit);
+        map.add_no_mods(KeyCode::F(2), Command::Save);
+        map.add_alt(KeyCode::Char('s'), Command::Save);
+
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(json, r#"{"F2":"Save","C-q":"Quit","M-s":"Save"}"#);
+        let loaded: KeyMap<Command> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.map(), map.map());
+
+        map.add_any(Command::Save);
+        let json = serde_json::to_string(&map).unwrap();
+        assert!(json.ends_with(r#","<any>":"Save"}"#), "{}", json);
+        let loaded: KeyMap<Command> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.fallback(), Some(&Command::Save));
+
+        assert!(serde_json::from_str::<KeyMap<Command>>(r#"{"C-Foo":"Quit"}"#).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn key_combination_serializes_as_string() {
+        let key = KeyCombination::Specific(KeyCode::Enter, Modifiers::default().ctrl());
+        let json = serde_json::to_string(&key).unwrap();
+        assert_eq!(json, r#""C-Enter""#);
+        assert_eq!(serde_json::from_str::<KeyCombination>(&json).unwrap(), key);
+
+        let modifiers = Modifiers::default().ctrl().shift();
+        let json = serde_json::to_string(&modifiers).unwrap();
+        assert_eq!(json, r#""C-Shift-""#);
+        assert_eq!(serde_json::from_str::<Modifiers>(&json).unwrap(), modifiers);
+        assert_eq!(serde_json::to_string(&Modifiers::default()).unwrap(), "\"\"");
+        assert!(serde_json::from_str::<Modifiers>(r#""C-x""#).is_err());
+    }
+
+    #[test]
+    fn remove_and_rebind_default_bindings() {
+        use crate::readline::Action as LineAction;
+
+        let mut map = crate::ReadLine::def_key_map().clone();
+        let ctrl_k = ctrl('k');
+        assert!(map.contains(&ctrl_k));
+        assert_eq!(map.remove(&ctrl_k), Some(LineAction::KillToEnd));
+        assert!(!map.contains(&ctrl_k));
+        assert_eq!(map.get_action(ctrl_key('k')), None);
+        assert_eq!(map.remove(&ctrl_k), None);
+
+        let ctrl_u = ctrl('u');
+        assert_eq!(
+            map.rebind(ctrl_u, LineAction::KillToEnd),
+            Some(LineAction::KillToStart)
+        );
+        assert_eq!(map.get_action(ctrl_key('u')), Some(&LineAction::KillToEnd));
+        assert_eq!(map.rebind(ctrl_k, LineAction::Yank), None);
+        assert_eq!(map.get_action(ctrl_key('k')), Some(&LineAction::Yank));
+    }
+
+    #[test]
+    fn bindings_sorted_is_deterministic() {
+        let mut map = KeyMap::new();
+        map.add_char_no_handler(Action::Save);
+        map.add_ctrl(KeyCode::Char('b'), Action::Quit);
+        map.add_no_mods(KeyCode::Enter, Action::Save);
+        map.add_no_mods(KeyCode::Char('b'), Action::Quit);
+        map.add_no_mods(KeyCode::Char('a'), Action::Quit);
+
+        let keys: Vec<String> = map
+            .bindings_sorted()
+            .iter()
+            .map(|(key, _)| format!("{}", key))
+            .collect();
+        assert_eq!(keys, ["Enter", "a", "b", "C-b", "<char>"]);
+        assert_eq!(map.iter().count(), 5);
+    }
+
+    #[test]
+    fn describe_joins_keys_of_an_action() {
+        let mut map = KeyMap::new();
+        map.add_ctrl(KeyCode::Char('q'), "quit");
+        map.add_no_mods(KeyCode::Esc, "quit");
+        map.add_no_mods(KeyCode::F(2), "save");
+
+        let entries = map.describe_entries();
+        assert_eq!(
+            entries,
+            [("C-q / Esc".to_owned(), &"quit"), ("F2".to_owned(), &"save")]
+        );
+
+        let mut output = String::new();
+        map.describe(&mut output);
+        assert_eq!(
+            output,
+            "    C-q / Esc          - quit\n    F2                 - save\n\n"
+        );
+    }
+
+    #[test]
+    fn unbound_key_resolves_to_fallback() {
+        let mut map = KeyMap::new();
+        map.add_ctrl(KeyCode::Char('q'), Action::Quit);
+        let up = KeyEvent::new(KeyCode::Up, KeyModifiers::NONE);
+        assert_eq!(map.get_action(up), None);
+
+        map.add_any(Action::Save);
+        assert_eq!(map.get_action(up), Some(&Action::Save));
+        assert_eq!(map.get_action(ctrl_key('q')), Some(&Action::Quit));
+        assert_eq!(map.remove_fallback(), Some(Action::Save));
+        assert_eq!(map.get_action(up), None);
+    }
+
+    #[test]
+    fn dumped_bindings_keep_fallback() {
+        let mut map = KeyMap::new();
+        map.add_ctrl(KeyCode::Char('q'), Action::Quit);
+        map.add_any(Action::Save);
+
+        let dumped = map.dump_bindings();
+        assert_eq!(
+            dumped,
+            vec![("C-q".to_string(), &Action::Quit), ("<any>".to_string(), &Action::Save)]
+        );
+        let loaded = KeyMap::load_bindings(dumped.into_iter().map(|(k, a)| (k, *a))).unwrap();
+        assert_eq!(loaded.map(), map.map());
+        assert_eq!(loaded.fallback(), Some(&Action::Save));
+    }
+
+    #[test]
+    fn double_bound_key_is_detected() {
+        let mut map = KeyMap::new();
+        assert_eq!(map.try_add(ctrl('q'), Action::Quit), Ok(()));
+        assert_eq!(map.try_add(ctrl('q'), Action::Save), Err(Action::Save));
+        assert_eq!(map.map().get(&ctrl('q')), Some(&Action::Quit));
+
+        let mut user = KeyMap::new();
+        user.add_ctrl(KeyCode::Char('s'), Action::Save);
+        user.add_ctrl(KeyCode::Char('q'), Action::Save);
+        assert_eq!(map.conflicts_with(&user), vec![&ctrl('q')]);
+        assert_eq!(user.conflicts_with(&KeyMap::<()>::new()), Vec::<&KeyCombination>::new());
+    }
+
+    /// A default map with C-q to quit, and user overrides binding it to save
+    fn colliding_maps() -> (KeyMap<Action>, KeyMap<Action>) {
+        let mut defaults = KeyMap::new();
+        defaults.add_ctrl(KeyCode::Char('q'), Action::Quit);
+        let mut user = KeyMap::new();
+        user.add_ctrl(KeyCode::Char('q'), Action::Save);
+        user.add_ctrl(KeyCode::Char('s'), Action::Save);
+        (defaults, user)
+    }
+
+    #[test]
+    fn merge_keep_existing() {
+        let (mut map, user) = colliding_maps();
+        map.merge(user, ConflictPolicy::KeepExisting).unwrap();
+        assert_eq!(map.get_action(ctrl_key('q')), Some(&Action::Quit));
+        assert_eq!(map.get_action(ctrl_key('s')), Some(&Action::Save));
+    }
+
+    #[test]
+    fn merge_overwrite() {
+        let (mut map, user) = colliding_maps();
+        map.merge(user, ConflictPolicy::Overwrite).unwrap();
+        assert_eq!(map.get_action(ctrl_key('q')), Some(&Action::Save));
+        assert_eq!(map.get_action(ctrl_key('s')), Some(&Action::Save));
+    }
+
+    #[test]
+    fn merge_error() {
+        let (mut map, user) = colliding_maps();
+        assert_eq!(
+            map.merge(user, ConflictPolicy::Error),
+            Err(MergeError::Conflict(ctrl('q')))
+        );
+        assert_eq!(map.map().len(), 1);
+
+        let mut other = KeyMap::new();
+        other.add_any(Action::Save);
+        map.add_any(Action::Quit);
+        assert_eq!(
+            map.merge(other, ConflictPolicy::Error),
+            Err(MergeError::ConflictingFallback)
+        );
+    }
+
+    #[test]
+    fn all_chars_display_keeps_modifiers() {
+        let key = KeyCombination::AllChars(Modifiers::default().ctrl());
+        assert_eq!(key.to_string(), "C-<char>");
+        assert_eq!(KeyCombination::AllChars(Modifiers::default()).to_string(), "<char>");
+        assert_eq!("C-<char>".parse(), Ok(key));
+    }
+
+    #[test]
+    fn bind_ctrl_shift_up() {
+        let mut map = KeyMap::new();
+        map.add(KeyCode::Up, Modifiers::default().ctrl().shift(), Action::Save);
+        let event = KeyEvent::new(KeyCode::Up, KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+        assert_eq!(map.get_action(event), Some(&Action::Save));
+        assert_eq!(map.get_action(KeyEvent::new(KeyCode::Up, KeyModifiers::CONTROL)), None);
+
+        // Shifted characters are bound as terminals report them.
+        map.add(KeyCode::Char('k'), Modifiers::default().ctrl().shift(), Action::Quit);
+        let event = KeyEvent::new(KeyCode::Char('K'), KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+        assert_eq!(map.get_action(event), Some(&Action::Quit));
+    }
+
+    #[test]
+    fn key_release_fires_nothing() {
+        let mut map = KeyMap::new();
+        map.add_ctrl(KeyCode::Char('q'), Action::Quit);
+        map.add_any(Action::Save);
+
+        let event = |kind| KeyEvent::new_with_kind(KeyCode::Char('q'), KeyModifiers::CONTROL, kind);
+        assert_eq!(map.get_action(event(KeyEventKind::Press)), Some(&Action::Quit));
+        assert_eq!(map.get_action(event(KeyEventKind::Repeat)), Some(&Action::Quit));
+        assert_eq!(map.get_action(event(KeyEventKind::Release)), None);
+    }
+}
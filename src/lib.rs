@@ -1,10 +1,26 @@
+pub mod backend;
+#[cfg(feature = "async")]
+pub mod eventloop;
+pub mod gauge;
+pub mod helpscreen;
 pub mod keyaction;
+pub mod list;
+pub mod mouseaction;
+pub mod progressbar;
 pub mod readline;
 pub mod renderer;
+pub mod spinner;
 
-pub use keyaction::{KeyCombination, KeyMap};
+pub use backend::{Backend, TestBackend};
+pub use gauge::Gauge;
+pub use helpscreen::HelpScreen;
+pub use keyaction::{ConflictPolicy, KeyCombination, KeyMap};
+pub use list::List;
+pub use mouseaction::MouseMap;
+pub use progressbar::ProgressBar;
 pub use readline::ReadLine;
-pub use renderer::Renderer;
+pub use renderer::{CellGrid, ColorMode, Frame, Pen, Renderer, Viewport};
+pub use spinner::Spinner;
 
 // Re-exports
 pub use crossterm::event::{KeyCode, KeyEvent, Event};
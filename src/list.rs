@@ -0,0 +1,180 @@
+//! Scrolling list with a selected row
+
+use super::{ContentStyle, Renderer};
+
+pub struct List<T: AsRef<str>> {
+    items: Vec<T>,
+    selected: usize,
+    /// Index of the first visible item
+    offset: usize,
+    /// Moving past either end continues at the other one
+    wrap: bool,
+}
+
+impl<T: AsRef<str>> List<T> {
+    pub fn new(items: Vec<T>) -> Self {
+        Self {
+            items,
+            selected: 0,
+            offset: 0,
+            wrap: false,
+        }
+    }
+
+    pub fn set_wrap(&mut self, wrap: bool) -> &mut Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    /// Replace the items, keeping the selection in range.
+    pub fn set_items(&mut self, items: Vec<T>) {
+        self.items = items;
+        self.select(self.selected);
+    }
+
+    /// Index of the selected item, `None` if the list is empty
+    pub fn selected(&self) -> Option<usize> {
+        if self.items.is_empty() {
+            None
+        } else {
+            Some(self.selected)
+        }
+    }
+
+    pub fn selected_item(&self) -> Option<&T> {
+        self.items.get(self.selected)
+    }
+
+    /// Select the item at `index`, clamped to the last one.
+    pub fn select(&mut self, index: usize) {
+        self.selected = index.min(self.items.len().saturating_sub(1));
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn move_up(&mut self) {
+        if self.selected > 0 {
+            self.selected -= 1;
+        } else if self.wrap {
+            self.select(usize::MAX);
+        }
+    }
+
+    pub fn move_down(&mut self) {
+        if self.selected + 1 < self.items.len() {
+            self.selected += 1;
+        } else if self.wrap {
+            self.selected = 0;
+        }
+    }
+
+    /// Draw the items visible in `h` rows, scrolling so the selected one is
+    /// among them. Items wider than `w` are cut with an ellipsis.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &mut self,
+        renderer: &mut Renderer,
+        x: u16,
+        y: u16,
+        w: u16,
+        h: u16,
+        item_style: ContentStyle,
+        selected_style: ContentStyle,
+    ) {
+        self.scroll_to_selected(h as usize);
+
+        let visible = self.items.iter().enumerate().skip(self.offset);
+        for ((index, item), row) in visible.zip(y..y.saturating_add(h)) {
+            let style = if index == self.selected {
+                renderer.fill_rect(x, row, w, 1, ' ', selected_style);
+                selected_style
+            } else {
+                item_style
+            };
+            renderer.draw_truncated(x, row, w, item.as_ref(), style);
+        }
+    }
+
+    fn scroll_to_selected(&mut self, height: usize) {
+        if height == 0 {
+            return;
+        }
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height;
+        }
+        // Don't leave rows empty when items were removed or the list grew taller.
+        self.offset = self.offset.min(self.items.len().saturating_sub(height));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestBackend;
+    use crossterm::style::{Attribute, Stylize};
+
+    fn list(n: usize) -> List<String> {
+        List::new((0..n).map(|i| format!("item {}", i)).collect())
+    }
+
+    fn draw(list: &mut List<String>, w: u16, h: u16) -> (Renderer, String) {
+        let mut renderer = Renderer::default();
+        renderer.probe_size(&TestBackend::new(w, h)).unwrap();
+        renderer.begin().unwrap();
+        let selected = ContentStyle::default().reverse();
+        list.draw(&mut renderer, 0, 0, w, h, ContentStyle::default(), selected);
+        let text = renderer.render_to_text();
+        (renderer, text)
+    }
+
+    #[test]
+    fn selection_clamps_or_wraps() {
+        let mut items = list(3);
+        items.move_up();
+        assert_eq!(items.selected(), Some(0));
+        items.move_down();
+        items.move_down();
+        items.move_down();
+        assert_eq!(items.selected(), Some(2));
+
+        items.set_wrap(true);
+        items.move_down();
+        assert_eq!(items.selected(), Some(0));
+        items.move_up();
+        assert_eq!(items.selected(), Some(2));
+
+        items.set_items(vec!["only".to_owned()]);
+        assert_eq!(items.selected(), Some(0));
+        items.set_items(vec![]);
+        assert_eq!(items.selected(), None);
+    }
+
+    #[test]
+    fn scrolls_to_keep_selection_visible() {
+        let mut items = list(6);
+        for _ in 0..4 {
+            items.move_down();
+        }
+        let (renderer, text) = draw(&mut items, 6, 3);
+        assert_eq!(items.offset(), 2);
+        assert_eq!(text, "item 2\nitem 3\nitem 4\n");
+        let frame = renderer.snapshot();
+        assert!(frame.cell(0, 2).unwrap().1.attributes.has(Attribute::Reverse));
+        assert!(!frame.cell(0, 1).unwrap().1.attributes.has(Attribute::Reverse));
+
+        for _ in 0..4 {
+            items.move_up();
+        }
+        let (_, text) = draw(&mut items, 5, 3);
+        assert_eq!(items.offset(), 0);
+        assert_eq!(text, "item…\nitem…\nitem…\n");
+    }
+}
@@ -0,0 +1,112 @@
+//! Types to manage mapping of mouse buttons to actions
+
+use super::keyaction::Modifiers;
+use crossterm::event::{MouseButton, MouseEvent, MouseEventKind};
+use std::collections::HashMap;
+
+#[derive(Debug, Hash, Copy, Clone, Eq, PartialEq)]
+pub enum MouseKind {
+    Down,
+    Up,
+    Drag,
+}
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
+pub struct MouseCombination {
+    pub button: MouseButton,
+    pub modifiers: Modifiers,
+    pub kind: MouseKind,
+}
+
+#[derive(Clone)]
+pub struct MouseMap<A> {
+    map: HashMap<MouseCombination, A>,
+}
+
+impl<A> Default for MouseMap<A> {
+    fn default() -> Self {
+        MouseMap::new()
+    }
+}
+
+impl<A> MouseMap<A> {
+    pub fn new() -> Self {
+        Self {
+            map: Default::default(),
+        }
+    }
+
+    pub fn map(&self) -> &HashMap<MouseCombination, A> {
+        &self.map
+    }
+
+    fn add(&mut self, button: MouseButton, modifiers: Modifiers, kind: MouseKind, a: A) {
+        self.map.insert(
+            MouseCombination {
+                button,
+                modifiers,
+                kind,
+            },
+            a,
+        );
+    }
+
+    pub fn add_click(&mut self, button: MouseButton, modifiers: Modifiers, a: A) {
+        self.add(button, modifiers, MouseKind::Down, a);
+    }
+
+    pub fn add_release(&mut self, button: MouseButton, modifiers: Modifiers, a: A) {
+        self.add(button, modifiers, MouseKind::Up, a);
+    }
+
+    pub fn add_drag(&mut self, button: MouseButton, modifiers: Modifiers, a: A) {
+        self.add(button, modifiers, MouseKind::Drag, a);
+    }
+
+    /// The action bound to a mouse event, along with the column and row it
+    /// happened at.
+    pub fn get_action(&self, mouse_event: &MouseEvent) -> Option<(&A, u16, u16)> {
+        let (button, kind) = match mouse_event.kind {
+            MouseEventKind::Down(button) => (button, MouseKind::Down),
+            MouseEventKind::Up(button) => (button, MouseKind::Up),
+            MouseEventKind::Drag(button) => (button, MouseKind::Drag),
+            _ => return None,
+        };
+        let combination = MouseCombination {
+            button,
+            modifiers: Modifiers::from(mouse_event.modifiers),
+            kind,
+        };
+        let action = self.map.get(&combination)?;
+        Some((action, mouse_event.column, mouse_event.row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn mouse(kind: MouseEventKind, column: u16, row: u16) -> MouseEvent {
+        MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    #[test]
+    fn left_click_maps_to_action() {
+        let mut map = MouseMap::new();
+        map.add_click(MouseButton::Left, Modifiers::from(KeyModifiers::NONE), "select");
+
+        let event = mouse(MouseEventKind::Down(MouseButton::Left), 3, 7);
+        assert_eq!(map.get_action(&event), Some((&"select", 3, 7)));
+
+        let release = mouse(MouseEventKind::Up(MouseButton::Left), 3, 7);
+        assert_eq!(map.get_action(&release), None);
+        let right = mouse(MouseEventKind::Down(MouseButton::Right), 3, 7);
+        assert_eq!(map.get_action(&right), None);
+    }
+}
@@ -0,0 +1,105 @@
+//! Horizontal progress bar with sub-cell resolution
+
+use super::{ContentStyle, Renderer};
+
+/// Left-aligned blocks covering 1/8 to 7/8 of a cell
+const PARTIAL_BLOCKS: [char; 7] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+
+#[derive(Default)]
+pub struct ProgressBar {
+    /// Overlay the percentage centered on the bar
+    label: bool,
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_label(&mut self, label: bool) -> &mut Self {
+        self.label = label;
+        self
+    }
+
+    /// Draw the bar filled to `fraction`, clamped to `[0, 1]`. The empty part
+    /// uses `style`, the filled part `filled_style`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        renderer: &mut Renderer,
+        x: u16,
+        y: u16,
+        width: u16,
+        fraction: f32,
+        style: ContentStyle,
+        filled_style: ContentStyle,
+    ) {
+        let fraction = if fraction.is_nan() {
+            0.0
+        } else {
+            fraction.clamp(0.0, 1.0)
+        };
+        let eighths = (fraction * width as f32 * 8.0).round() as u32;
+        let full = (eighths / 8) as u16;
+        let partial = (eighths % 8) as usize;
+
+        for i in 0..width {
+            if i < full {
+                renderer.draw_char(x + i, y, '█', filled_style);
+            } else if i == full && partial > 0 {
+                renderer.draw_char(x + i, y, PARTIAL_BLOCKS[partial - 1], filled_style);
+            } else {
+                renderer.draw_char(x + i, y, ' ', style);
+            }
+        }
+
+        if self.label {
+            let label = format!("{}%", (fraction * 100.0).round());
+            let len = label.len() as u16;
+            if len > width {
+                return;
+            }
+            let start = (width - len) / 2;
+            for (i, c) in (start..).zip(label.chars()) {
+                let style = if i < full { filled_style } else { style };
+                renderer.draw_char(x + i, y, c, style);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestBackend;
+
+    fn drawn(bar: &ProgressBar, width: u16, fraction: f32) -> String {
+        let mut renderer = Renderer::default();
+        renderer.probe_size(&TestBackend::new(width, 1)).unwrap();
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        bar.draw(&mut renderer, 0, 0, width, fraction, style, style);
+        renderer.render_to_text().trim_end().to_owned()
+    }
+
+    #[test]
+    fn half_fills_half_the_cells() {
+        assert_eq!(drawn(&ProgressBar::new(), 10, 0.5), "█████");
+    }
+
+    #[test]
+    fn partial_blocks_and_clamping() {
+        let bar = ProgressBar::new();
+        assert_eq!(drawn(&bar, 4, 0.3), "█▎");
+        assert_eq!(drawn(&bar, 4, 1.5), "████");
+        assert_eq!(drawn(&bar, 4, -1.0), "");
+        assert_eq!(drawn(&bar, 4, f32::NAN), "");
+    }
+
+    #[test]
+    fn label_is_centered() {
+        let mut bar = ProgressBar::new();
+        bar.set_label(true);
+        assert_eq!(drawn(&bar, 10, 0.5), "███50%");
+    }
+}
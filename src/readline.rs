@@ -1,224 +1,1688 @@
-//! Single line editor widget
-
-use super::{KeyCode, KeyEvent};
-
-pub enum Action {
-    BackDeleteChar,
-    DeleteChar,
-    LeftChar,
-    LeftWord,
-    RightChar,
-    RightWord,
-    DelBackWord,
-    GotoLineStart,
-    GotoLineEnd,
-    InsertChar,
-    Complete,
-}
-
-pub struct ReadLine {
-    /// Cursor position
-    cursor: u16,
-    h_scroll: u16,
-    strval: String,
-}
-
-pub struct StyleMap {
-    pub main: ansi_term::Style,
-    pub overflow: ansi_term::Style,
-}
-
-pub type KeyMap = super::keyaction::KeyMap<Action>;
-
-lazy_static::lazy_static! {
-    static ref DEF_STYLE_MAP : StyleMap = {
-        StyleMap {
-            main: Default::default(),
-            overflow: Default::default(),
-        }
-    };
-
-    static ref DEF_KEY_MAP : KeyMap = {
-        let mut m : KeyMap = KeyMap::new();
-
-        m.add_no_mods(KeyCode::Backspace, Action::BackDeleteChar);
-        m.add_no_mods(KeyCode::Delete, Action::DeleteChar);
-        m.add_no_mods(KeyCode::Left, Action::LeftChar);
-        m.add_no_mods(KeyCode::Right, Action::RightChar);
-        m.add_ctrl(KeyCode::Left, Action::LeftWord);
-        m.add_ctrl(KeyCode::Right, Action::RightWord);
-        m.add_ctrl(KeyCode::Char('w'), Action::DelBackWord);
-        m.add_no_mods(KeyCode::Home, Action::GotoLineStart);
-        m.add_no_mods(KeyCode::End, Action::GotoLineEnd);
-        m.add_char_no_handler(Action::InsertChar);
-        m.add_char_shift(Action::InsertChar);
-        m.add_no_mods(KeyCode::Tab, Action::Complete);
-
-        m
-    };
-}
-
-impl ReadLine {
-    pub fn def_style_map() -> &'static StyleMap {
-        &*DEF_STYLE_MAP
-    }
-
-    pub fn def_key_map() -> &'static KeyMap {
-        &*DEF_KEY_MAP
-    }
-
-    pub fn new() -> Self {
-        Self {
-            cursor: 0,
-            h_scroll: 0,
-            strval: Default::default(),
-        }
-    }
-
-    pub fn strval(&self) -> &str {
-        &self.strval
-    }
-
-    pub fn draw(
-        &self,
-        x: u16,
-        y: u16,
-        _length: u16,
-        renderer: &mut super::Renderer,
-        style_map: &StyleMap,
-    ) {
-        use ansi_term::ANSIStrings;
-        let mut v = vec![];
-
-        v.push(style_map.main.paint(&self.strval));
-
-        renderer.draw(x, y, ANSIStrings(v.as_slice()));
-    }
-
-    pub fn get_cursor(&self) -> u16 {
-        self.cursor - self.h_scroll
-    }
-
-    fn cursor(&self) -> usize {
-        std::cmp::min(self.cursor as usize, self.strval.len())
-    }
-
-    pub fn apply_action(&mut self, action: &Action, event: KeyEvent) {
-        match action {
-            Action::InsertChar => {
-                if let KeyCode::Char(c) = event.code {
-                    let cursor = self.cursor();
-                    self.strval =
-                        format!("{}{}{}", &self.strval[..cursor], c, &self.strval[cursor..]);
-                    self.cursor += 1;
-                }
-            }
-            Action::BackDeleteChar => {
-                let cursor = self.cursor();
-                if cursor > 0 {
-                    self.strval =
-                        format!("{}{}", &self.strval[..cursor - 1], &self.strval[cursor..]);
-                    self.cursor = (cursor - 1) as u16;
-                }
-            }
-            Action::DeleteChar => {
-                let cursor = self.cursor();
-                if cursor < self.strval.len() {
-                    self.strval =
-                        format!("{}{}", &self.strval[..cursor], &self.strval[cursor + 1..]);
-                    self.cursor = self.cursor() as u16;
-                }
-            }
-            Action::LeftChar => {
-                let cursor = self.cursor();
-                if cursor > 0 {
-                    self.cursor = (cursor - 1) as u16;
-                }
-            }
-            Action::LeftWord => {
-                if let Some(cursor) = self.left_word_offset() {
-                    self.cursor = cursor as u16;
-                }
-            }
-            Action::RightWord => {
-                if let Some(cursor) = self.right_word_offset() {
-                    self.cursor = cursor as u16;
-                }
-            }
-            Action::DelBackWord => {
-                let cur_cursor = self.cursor();
-                if let Some(cursor) = self.left_word_offset() {
-                    self.strval =
-                        format!("{}{}", &self.strval[..cursor], &self.strval[cur_cursor..]);
-                    self.cursor = cursor as u16;
-                }
-            }
-            Action::GotoLineStart => {
-                self.cursor = 0;
-            }
-            Action::GotoLineEnd => {
-                self.cursor = self.strval.len() as u16;
-            }
-            Action::RightChar => {
-                self.cursor = (self.cursor() + 1) as u16;
-                self.cursor = self.cursor() as u16;
-            }
-            Action::Complete => {}
-        }
-    }
-
-    fn left_word_offset(&self) -> Option<usize> {
-        let v: Vec<_> = self.strval.chars().collect();
-        let cursor = self.cursor();
-        if cursor > 0 {
-            let mut cursor = cursor - 1;
-            while cursor > 0 {
-                if v[cursor] == ' ' {
-                    cursor -= 1;
-                } else {
-                    break;
-                }
-            }
-            let mut prev_cursor = cursor;
-            loop {
-                if cursor < v.len() && v[cursor] != ' ' {
-                    prev_cursor = cursor;
-                    if cursor == 0 {
-                        break;
-                    }
-                    cursor -= 1;
-                } else {
-                    break;
-                }
-            }
-            return Some(prev_cursor);
-        }
-
-        None
-    }
-
-    fn right_word_offset(&self) -> Option<usize> {
-        let v: Vec<_> = self.strval.chars().collect();
-        let cursor = self.cursor();
-        if cursor < v.len() {
-            let mut cursor = cursor;
-            while cursor < v.len() {
-                if v[cursor] != ' ' {
-                    cursor += 1;
-                } else {
-                    break;
-                }
-            }
-            while cursor < v.len() {
-                if v[cursor] == ' ' {
-                    cursor += 1;
-                } else {
-                    break;
-                }
-            }
-            return Some(cursor);
-        }
-
-        None
-    }
-}
+//! Single line editor widget
+
+use super::{KeyCode, KeyEvent};
+use unicode_normalization::UnicodeNormalization;
+use unicode_width::UnicodeWidthChar;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    BackDeleteChar,
+    DeleteChar,
+    LeftChar,
+    LeftWord,
+    RightChar,
+    RightWord,
+    DelBackWord,
+    KillToEnd,
+    KillToStart,
+    Yank,
+    GotoLineStart,
+    GotoLineEnd,
+    InsertChar,
+    Complete,
+    Undo,
+    Redo,
+    HistoryPrev,
+    HistoryNext,
+    HistorySearch,
+    Cancel,
+    TransposeChars,
+    SetMark,
+    CutRegion,
+    CopyRegion,
+    /// Submit the line, reported by `handle_key`
+    Accept,
+}
+
+/// What a key did to the line, as reported by `handle_key`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// Enter was pressed on valid text, which is passed along
+    Submitted(String),
+    Edited,
+    /// Esc was pressed outside of a history search
+    Cancelled,
+    /// The action was refused, such as inserting past the maximum length or
+    /// submitting text that fails validation
+    Refused,
+}
+
+/// What the `Complete` action (bound to Tab) does
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TabAction {
+    /// Request completion; nothing happens without a completer
+    Complete,
+    /// Insert spaces up to the next multiple of the given width
+    InsertSpaces(u16),
+    /// Insert a literal tab character
+    InsertTab,
+}
+
+/// Unicode normalization form that inserted text is converted to
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NormalizationForm {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+}
+
+impl NormalizationForm {
+    fn apply(self, s: &str) -> String {
+        match self {
+            NormalizationForm::Nfc => s.nfc().collect(),
+            NormalizationForm::Nfd => s.nfd().collect(),
+            NormalizationForm::Nfkc => s.nfkc().collect(),
+            NormalizationForm::Nfkd => s.nfkd().collect(),
+        }
+    }
+}
+
+/// Completion candidate being previewed inline
+struct Completion {
+    /// Suffixes that each candidate adds to the text before the cursor
+    suffixes: Vec<String>,
+    index: usize,
+    /// Where the previewed suffix starts in `strval`
+    start: usize,
+}
+
+/// Incremental search through history in progress
+struct Search {
+    query: String,
+    /// Text and cursor to restore if the search is cancelled
+    saved: (String, u16),
+    /// History entry matched, if any
+    index: Option<usize>,
+    /// Whether the last change to the query found nothing
+    failing: bool,
+}
+
+pub struct ReadLine {
+    /// Cursor position
+    cursor: u16,
+    h_scroll: u16,
+    strval: String,
+    /// Character drawn in place of each input character, for passwords
+    mask: Option<char>,
+    /// Produces the text shown right-aligned after the input, e.g. a word count
+    counter_fn: Option<CounterFn>,
+    /// Maximum number of characters the buffer may hold
+    max_len: Option<usize>,
+    /// Text removed by the last kill action
+    kill_ring: String,
+    tab_action: TabAction,
+    /// Snapshots of `(strval, cursor)` taken before each edit
+    undo_stack: Vec<(String, u16)>,
+    redo_stack: Vec<(String, u16)>,
+    /// Whether the last edit was an insertion that the next one may join
+    coalesce_insert: bool,
+    normalization: Option<NormalizationForm>,
+    complete_fn: Option<CompleteFn>,
+    completion: Option<Completion>,
+    /// Columns kept visible on either side of the cursor when scrolling
+    scroll_margin: u16,
+    /// Previously entered lines, oldest first
+    history: Vec<String>,
+    /// Entry being shown, or `None` when editing a new line
+    history_index: Option<usize>,
+    /// The new line, kept while browsing history
+    history_draft: String,
+    search: Option<Search>,
+    /// Shown in place of the text while there is none
+    placeholder: Option<String>,
+    validate_fn: Option<ValidateFn>,
+    word_boundary: Option<WordBoundary>,
+    prompt: Option<String>,
+    /// Other end of the region from the cursor, set by `SetMark`
+    mark: Option<usize>,
+    /// Why the text was last found invalid
+    error: Option<String>,
+}
+
+/// Maximum number of undo steps kept
+const UNDO_LIMIT: usize = 100;
+
+impl Default for ReadLine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct StyleMap {
+    pub main: ansi_term::Style,
+    pub prompt: ansi_term::Style,
+    pub overflow: ansi_term::Style,
+    pub counter: ansi_term::Style,
+    /// Used for the inline completion preview
+    pub completion: ansi_term::Style,
+    /// Used for the history search prompt and the matched text
+    pub search: ansi_term::Style,
+    pub placeholder: ansi_term::Style,
+    /// Used instead of `main` while the text fails validation
+    pub error: ansi_term::Style,
+    /// Used for the region between the mark and the cursor
+    pub selection: ansi_term::Style,
+}
+
+pub type KeyMap = super::keyaction::KeyMap<Action>;
+
+pub type CounterFn = Box<dyn Fn(&str) -> String>;
+
+/// Returns completion candidates for the text before the cursor. Only
+/// candidates extending that text are offered.
+pub type CompleteFn = Box<dyn Fn(&str) -> Vec<String>>;
+
+/// Checks the text, describing what is wrong with it if it's invalid.
+pub type ValidateFn = Box<dyn Fn(&str) -> Result<(), String>>;
+
+/// Classifies characters for the word motions and `DelBackWord`, e.g. to
+/// stop at `/` in paths: `Box::new(|c| if c == '/' { CharClass::Space }
+/// else { CharClass::of(c) })`.
+pub type WordBoundary = Box<dyn Fn(char) -> CharClass>;
+
+lazy_static::lazy_static! {
+    static ref DEF_STYLE_MAP : StyleMap = {
+        StyleMap {
+            main: Default::default(),
+            prompt: Default::default(),
+            overflow: Default::default(),
+            counter: Default::default(),
+            completion: ansi_term::Style::new().reverse(),
+            search: ansi_term::Style::new().bold(),
+            placeholder: ansi_term::Style::new().dimmed(),
+            error: ansi_term::Colour::Red.normal(),
+            selection: ansi_term::Style::new().reverse(),
+        }
+    };
+
+    static ref DEF_KEY_MAP : KeyMap = {
+        let mut m : KeyMap = KeyMap::new();
+
+        m.add_no_mods(KeyCode::Backspace, Action::BackDeleteChar);
+        m.add_no_mods(KeyCode::Delete, Action::DeleteChar);
+        m.add_no_mods(KeyCode::Left, Action::LeftChar);
+        m.add_no_mods(KeyCode::Right, Action::RightChar);
+        m.add_ctrl(KeyCode::Left, Action::LeftWord);
+        m.add_ctrl(KeyCode::Right, Action::RightWord);
+        m.add_ctrl(KeyCode::Char('w'), Action::DelBackWord);
+        m.add_ctrl(KeyCode::Char('k'), Action::KillToEnd);
+        m.add_ctrl(KeyCode::Char('u'), Action::KillToStart);
+        m.add_ctrl(KeyCode::Char('y'), Action::Yank);
+        m.add_no_mods(KeyCode::Home, Action::GotoLineStart);
+        m.add_no_mods(KeyCode::End, Action::GotoLineEnd);
+        m.add_char_no_handler(Action::InsertChar);
+        m.add_char_shift(Action::InsertChar);
+        m.add_no_mods(KeyCode::Tab, Action::Complete);
+        m.add_ctrl(KeyCode::Char('/'), Action::Undo);
+        m.add_ctrl(KeyCode::Char('_'), Action::Redo);
+        m.add_no_mods(KeyCode::Up, Action::HistoryPrev);
+        m.add_no_mods(KeyCode::Down, Action::HistoryNext);
+        m.add_ctrl(KeyCode::Char('r'), Action::HistorySearch);
+        m.add_no_mods(KeyCode::Esc, Action::Cancel);
+        m.add_no_mods(KeyCode::Enter, Action::Accept);
+        m.add_ctrl(KeyCode::Char('t'), Action::TransposeChars);
+        m.add_ctrl(KeyCode::Char(' '), Action::SetMark);
+        m.add_ctrl(KeyCode::Char('x'), Action::CutRegion);
+        m.add_alt(KeyCode::Char('w'), Action::CopyRegion);
+
+        m
+    };
+}
+
+impl ReadLine {
+    pub fn def_style_map() -> &'static StyleMap {
+        &DEF_STYLE_MAP
+    }
+
+    pub fn def_key_map() -> &'static KeyMap {
+        &DEF_KEY_MAP
+    }
+
+    pub fn new() -> Self {
+        Self {
+            cursor: 0,
+            h_scroll: 0,
+            strval: Default::default(),
+            mask: None,
+            counter_fn: None,
+            max_len: None,
+            kill_ring: String::new(),
+            tab_action: TabAction::Complete,
+            undo_stack: vec![],
+            redo_stack: vec![],
+            coalesce_insert: false,
+            normalization: None,
+            complete_fn: None,
+            completion: None,
+            scroll_margin: 0,
+            history: vec![],
+            history_index: None,
+            history_draft: String::new(),
+            search: None,
+            placeholder: None,
+            validate_fn: None,
+            word_boundary: None,
+            prompt: None,
+            mark: None,
+            error: None,
+        }
+    }
+
+    pub fn strval(&self) -> &str {
+        &self.strval
+    }
+
+    /// Insert text at the cursor as a single edit, such as the contents of
+    /// an `Event::Paste`. Line breaks become spaces and other control
+    /// characters besides tabs are dropped. Returns `false` if the text
+    /// would exceed the maximum length.
+    pub fn insert_str(&mut self, text: &str) -> bool {
+        self.completion = None;
+        self.search = None;
+        self.coalesce_insert = false;
+
+        let text: String = text
+            .chars()
+            .filter(|c| *c != '\r')
+            .map(|c| if c == '\n' { ' ' } else { c })
+            .filter(|c| !c.is_control() || *c == '\t')
+            .collect();
+        let snapshot = (self.strval.clone(), self.cursor);
+        let applied = self.insert(&text);
+        if self.strval != snapshot.0 {
+            self.push_undo(snapshot);
+            self.redo_stack.clear();
+        }
+        self.validate();
+        applied
+    }
+
+    /// Check the text after every action, for `is_valid` and `error`.
+    pub fn set_validator(&mut self, validate_fn: Option<ValidateFn>) {
+        self.validate_fn = validate_fn;
+        self.validate();
+    }
+
+    /// Replace the default classification of characters into words.
+    pub fn set_word_boundary(&mut self, word_boundary: Option<WordBoundary>) {
+        self.word_boundary = word_boundary;
+    }
+
+    fn char_classes(&self) -> Vec<CharClass> {
+        match &self.word_boundary {
+            Some(word_boundary) => self.strval.chars().map(word_boundary).collect(),
+            None => self.strval.chars().map(CharClass::of).collect(),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    fn validate(&mut self) {
+        self.error = match &self.validate_fn {
+            Some(validate_fn) => validate_fn(&self.strval).err(),
+            None => None,
+        };
+    }
+
+    /// Text drawn before the input, which `draw` and `get_cursor` account for.
+    pub fn set_prompt(&mut self, prompt: &str) {
+        self.prompt = Some(prompt.to_owned()).filter(|prompt| !prompt.is_empty());
+    }
+
+    /// Text shown dimmed while the input is empty, e.g. "search…"
+    pub fn set_placeholder(&mut self, placeholder: Option<String>) {
+        self.placeholder = placeholder;
+    }
+
+    /// Replace the text, e.g. to preload a default, with the cursor at the end.
+    pub fn set_value(&mut self, value: &str) {
+        self.strval = value.to_owned();
+        self.cursor = self.strval.chars().count() as u16;
+        self.h_scroll = 0;
+        self.mark = None;
+        self.completion = None;
+        self.search = None;
+        self.coalesce_insert = false;
+        self.validate();
+    }
+
+    pub fn clear(&mut self) {
+        self.set_value("");
+    }
+
+    pub fn set_mask(&mut self, mask: Option<char>) {
+        self.mask = mask;
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.strval.split_whitespace().count()
+    }
+
+    pub fn set_counter_fn(&mut self, counter_fn: CounterFn) {
+        self.counter_fn = Some(counter_fn);
+    }
+
+    pub fn set_max_len(&mut self, max_len: Option<usize>) {
+        self.max_len = max_len;
+    }
+
+    /// Normalize text as it is inserted, keeping the buffer in one form.
+    pub fn set_normalization(&mut self, normalization: Option<NormalizationForm>) {
+        self.normalization = normalization;
+    }
+
+    /// Set the source of candidates for `Complete`. Each `Complete` shows
+    /// the next candidate inline after the cursor; any other action, or
+    /// `accept_completion`, accepts the one shown.
+    pub fn set_completer(&mut self, complete_fn: Option<CompleteFn>) {
+        self.complete_fn = complete_fn;
+    }
+
+    /// Accept the completion candidate previewed inline, if any. Returns
+    /// whether one was shown, e.g. so that Enter only submits otherwise.
+    pub fn accept_completion(&mut self) -> bool {
+        self.completion.take().is_some()
+    }
+
+    pub fn set_tab_action(&mut self, tab_action: TabAction) {
+        self.tab_action = tab_action;
+    }
+
+    pub fn set_scroll_margin(&mut self, scroll_margin: u16) {
+        self.scroll_margin = scroll_margin;
+    }
+
+    /// Add a line to the history recalled by `HistoryPrev`, and go back to
+    /// editing a new line. Empty lines and repeats of the last are skipped.
+    pub fn push_history(&mut self, line: &str) {
+        self.history_index = None;
+        if line.is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_owned());
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// End a history search, keeping the matched line. Returns whether a
+    /// search was in progress, e.g. so that Enter only submits otherwise.
+    pub fn accept_search(&mut self) -> bool {
+        self.search.take().is_some()
+    }
+
+    /// Draw the prompt and input in `length` columns at `(x, y)`. Returns
+    /// the screen column of the cursor.
+    pub fn draw(
+        &mut self,
+        x: u16,
+        y: u16,
+        length: u16,
+        renderer: &mut super::Renderer,
+        style_map: &StyleMap,
+    ) -> u16 {
+        use ansi_term::ANSIStrings;
+
+        let mut length = length;
+        if let Some(counter_fn) = &self.counter_fn {
+            let counter = counter_fn(&self.strval);
+            let counter_width: u16 = counter.chars().map(char_width).sum();
+            if counter_width > 0 && counter_width <= length {
+                length -= counter_width;
+                renderer.draw(x + length, y, style_map.counter.paint(counter));
+                length = length.saturating_sub(1);
+            }
+        }
+
+        let mut x = x;
+        if let Some(prefix) = self.prefix() {
+            let style = match self.search {
+                Some(_) => &style_map.search,
+                None => &style_map.prompt,
+            };
+            let width = renderer.draw(x, y, style.paint(prefix));
+            x += width;
+            length = length.saturating_sub(width);
+        }
+
+        // The margin shrinks on narrow fields so the cursor always fits.
+        let margin = std::cmp::min(self.scroll_margin, length.saturating_sub(1) / 2);
+        let cursor_column = self.cursor_column();
+        if cursor_column < self.h_scroll + margin {
+            self.h_scroll = cursor_column.saturating_sub(margin);
+        } else if length > 0 && cursor_column + margin >= self.h_scroll + length {
+            self.h_scroll = cursor_column + margin + 1 - length;
+            // Don't scroll further than needed to show the end of the text.
+            let text_width: u16 = self.displayed().chars().map(char_width).sum();
+            let max_scroll = (text_width + 1).saturating_sub(length);
+            self.h_scroll = std::cmp::min(self.h_scroll, max_scroll);
+        }
+
+        let search = self.search.as_ref().filter(|search| search.index.is_some());
+        let (highlight, highlight_style) = if let Some(completion) = &self.completion {
+            let start = self.strval[..completion.start].chars().count();
+            let end = start + completion.suffixes[completion.index].chars().count();
+            (Some(start..end), &style_map.completion)
+        } else if let Some(search) = search {
+            let start = self.cursor();
+            let end = start + search.query.chars().count();
+            (Some(start..end), &style_map.search)
+        } else {
+            (self.region(), &style_map.selection)
+        };
+        let main_style = match self.error {
+            Some(_) => &style_map.error,
+            None => &style_map.main,
+        };
+
+        let mut spans: Vec<(String, &ansi_term::Style)> = vec![];
+        let mut column = 0;
+        for (i, c) in self.displayed().chars().enumerate() {
+            let width = char_width(c);
+            if column >= self.h_scroll {
+                if column + width > self.h_scroll + length {
+                    break;
+                }
+                let style = match &highlight {
+                    Some(highlight) if highlight.contains(&i) => highlight_style,
+                    _ => main_style,
+                };
+                match spans.last_mut() {
+                    Some((span, span_style)) if *span_style == style => span.push(c),
+                    _ => spans.push((c.to_string(), style)),
+                }
+            }
+            column += width;
+        }
+
+        if let (true, Some(placeholder)) = (self.strval.is_empty(), &self.placeholder) {
+            let mut width = 0;
+            let shown: String = placeholder
+                .chars()
+                .take_while(|c| {
+                    width += char_width(*c);
+                    width <= length
+                })
+                .collect();
+            renderer.draw(x, y, style_map.placeholder.paint(shown));
+            return x;
+        }
+
+        let v: Vec<_> = spans
+            .iter()
+            .map(|(span, style)| style.paint(span.as_str()))
+            .collect();
+
+        renderer.draw(x, y, ANSIStrings(v.as_slice()));
+        x + self.cursor_column().saturating_sub(self.h_scroll)
+    }
+
+    pub fn get_cursor(&self) -> u16 {
+        let prefix_width = self.prefix().map_or(0, |prefix| prefix.chars().map(char_width).sum());
+        prefix_width + self.cursor_column().saturating_sub(self.h_scroll)
+    }
+
+    /// Text drawn before the input: the prompt, or the history search
+    /// prompt in its place
+    fn prefix(&self) -> Option<String> {
+        match &self.search {
+            Some(search) => {
+                let failed = if search.failing { "failed " } else { "" };
+                Some(format!("({}reverse-i-search)`{}': ", failed, search.query))
+            }
+            None => self.prompt.clone(),
+        }
+    }
+
+    /// The text as shown on screen, which differs from `strval` when masked
+    fn displayed(&self) -> String {
+        match self.mask {
+            Some(mask) => self.strval.chars().map(|_| mask).collect(),
+            None => self.strval.clone(),
+        }
+    }
+
+    fn cursor_column(&self) -> u16 {
+        match self.mask {
+            Some(mask) => char_width(mask) * self.cursor() as u16,
+            None => self.strval.chars().take(self.cursor()).map(char_width).sum(),
+        }
+    }
+
+    /// The cursor as an index of characters, clamped to the text
+    fn cursor(&self) -> usize {
+        std::cmp::min(self.cursor as usize, self.strval.chars().count())
+    }
+
+    /// Byte offset in `strval` of the character at `index`
+    fn byte_offset(&self, index: usize) -> usize {
+        self.strval
+            .char_indices()
+            .nth(index)
+            .map_or(self.strval.len(), |(offset, _)| offset)
+    }
+
+    /// Applies an action to the buffer. Returns `false` if the action was
+    /// refused, such as inserting past the maximum length.
+    pub fn apply_action(&mut self, action: &Action, event: KeyEvent) -> bool {
+        let applied = self.apply(action, event);
+        self.validate();
+        applied
+    }
+
+    /// Apply the action bound to a key in the default key map, for hosts
+    /// that leave Enter and Esc to the line. Enter first accepts a completion
+    /// previewed inline, and only submits once none is. Returns `None` for
+    /// keys without a binding.
+    pub fn handle_key(&mut self, event: KeyEvent) -> Option<Outcome> {
+        let action = Self::def_key_map().get_action(event)?;
+        if let Action::Accept = action {
+            if self.accept_completion() {
+                return Some(Outcome::Edited);
+            }
+        }
+        let searching = self.search.is_some();
+        let applied = self.apply_action(action, event);
+        Some(match action {
+            Action::Accept if !self.is_valid() => Outcome::Refused,
+            Action::Accept => Outcome::Submitted(self.strval.clone()),
+            Action::Cancel if !searching => Outcome::Cancelled,
+            _ if !applied => Outcome::Refused,
+            _ => Outcome::Edited,
+        })
+    }
+
+    fn apply(&mut self, action: &Action, event: KeyEvent) -> bool {
+        if self.search.is_some() {
+            if let Some(applied) = self.search_action(action, event) {
+                return applied;
+            }
+            self.search = None;
+        }
+
+        if !matches!(action, Action::Complete) {
+            self.completion = None;
+        }
+
+        match action {
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            _ => {
+                // A run of insertions is undone as one, so only its first
+                // needs a snapshot.
+                let insert = matches!(action, Action::InsertChar);
+                let snapshot = if insert && self.coalesce_insert {
+                    None
+                } else {
+                    Some((self.strval.clone(), self.cursor))
+                };
+                let applied = self.edit(action, event);
+                let changed = match &snapshot {
+                    Some((strval, _)) => self.strval != *strval,
+                    None => applied,
+                };
+                if changed {
+                    self.mark = None;
+                    if let Some(snapshot) = snapshot {
+                        self.push_undo(snapshot);
+                    }
+                    self.redo_stack.clear();
+                }
+                self.coalesce_insert = insert && changed;
+                applied
+            }
+        }
+    }
+
+    /// Handle an action during a history search. Returns `None` for actions
+    /// that end the search and apply as usual.
+    fn search_action(&mut self, action: &Action, event: KeyEvent) -> Option<bool> {
+        let search = self.search.as_mut()?;
+        match action {
+            Action::InsertChar => {
+                if let KeyCode::Char(c) = event.code {
+                    search.query.push(c);
+                }
+                Some(self.search_from(self.history.len()))
+            }
+            Action::BackDeleteChar => {
+                search.query.pop();
+                Some(self.search_from(self.history.len()))
+            }
+            Action::HistorySearch => {
+                let start = search.index.unwrap_or(self.history.len());
+                Some(self.search_from(start))
+            }
+            Action::Cancel => {
+                let search = self.search.take()?;
+                self.strval = search.saved.0;
+                self.cursor = search.saved.1;
+                self.undo_stack.pop();
+                Some(true)
+            }
+            _ => None,
+        }
+    }
+
+    /// Show the most recent history entry before `end` that contains the
+    /// query, with the cursor at the match.
+    fn search_from(&mut self, end: usize) -> bool {
+        let search = match &mut self.search {
+            Some(search) => search,
+            None => return false,
+        };
+        if search.query.is_empty() {
+            search.index = None;
+            search.failing = false;
+            self.strval = search.saved.0.clone();
+            self.cursor = search.saved.1;
+            return true;
+        }
+
+        for index in (0..end).rev() {
+            if let Some(offset) = self.history[index].find(&search.query) {
+                search.index = Some(index);
+                search.failing = false;
+                self.strval = self.history[index].clone();
+                self.cursor = self.strval[..offset].chars().count() as u16;
+                return true;
+            }
+        }
+        search.failing = true;
+        false
+    }
+
+    fn complete(&mut self) -> bool {
+        if let Some(completion) = &mut self.completion {
+            let start = completion.start;
+            let end = start + completion.suffixes[completion.index].len();
+            completion.index = (completion.index + 1) % completion.suffixes.len();
+            let suffix = &completion.suffixes[completion.index];
+            self.strval.replace_range(start..end, suffix);
+            self.cursor = self.strval[..start + suffix.len()].chars().count() as u16;
+            return true;
+        }
+
+        let complete_fn = match &self.complete_fn {
+            Some(complete_fn) => complete_fn,
+            None => return false,
+        };
+        let start = self.byte_offset(self.cursor());
+        let base = &self.strval[..start];
+        // Candidates are held to the same rules as typed text.
+        let room = self
+            .max_len
+            .map_or(usize::MAX, |max_len| max_len.saturating_sub(self.strval.chars().count()));
+        let suffixes: Vec<String> = complete_fn(base)
+            .iter()
+            .map(|candidate| match self.normalization {
+                Some(normalization) => normalization.apply(candidate),
+                None => candidate.clone(),
+            })
+            .filter_map(|candidate| Some(candidate.strip_prefix(base)?.to_owned()))
+            .filter(|suffix| !suffix.is_empty())
+            .filter(|suffix| suffix.chars().count() <= room)
+            .collect();
+        if suffixes.is_empty() {
+            return false;
+        }
+
+        self.strval.insert_str(start, &suffixes[0]);
+        self.cursor = (self.cursor() + suffixes[0].chars().count()) as u16;
+        self.completion = Some(Completion {
+            suffixes,
+            index: 0,
+            start,
+        });
+        true
+    }
+
+    fn push_undo(&mut self, snapshot: (String, u16)) {
+        self.undo_stack.push(snapshot);
+        if self.undo_stack.len() > UNDO_LIMIT {
+            self.undo_stack.remove(0);
+        }
+    }
+
+    /// Characters between the mark and the cursor
+    fn region(&self) -> Option<std::ops::Range<usize>> {
+        let mark = std::cmp::min(self.mark?, self.strval.chars().count());
+        let cursor = self.cursor();
+        match mark.cmp(&cursor) {
+            std::cmp::Ordering::Less => Some(mark..cursor),
+            std::cmp::Ordering::Equal => None,
+            std::cmp::Ordering::Greater => Some(cursor..mark),
+        }
+    }
+
+    fn undo(&mut self) -> bool {
+        self.coalesce_insert = false;
+        self.mark = None;
+        match self.undo_stack.pop() {
+            Some((strval, cursor)) => {
+                let current = std::mem::replace(&mut self.strval, strval);
+                self.redo_stack.push((current, self.cursor));
+                self.cursor = cursor;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn redo(&mut self) -> bool {
+        self.coalesce_insert = false;
+        self.mark = None;
+        match self.redo_stack.pop() {
+            Some((strval, cursor)) => {
+                let current = std::mem::replace(&mut self.strval, strval);
+                self.undo_stack.push((current, self.cursor));
+                self.cursor = cursor;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn edit(&mut self, action: &Action, event: KeyEvent) -> bool {
+        match action {
+            Action::InsertChar => {
+                if let KeyCode::Char(c) = event.code {
+                    return self.insert(c.encode_utf8(&mut [0; 4]));
+                }
+                return false;
+            }
+            Action::BackDeleteChar => {
+                let cursor = self.cursor();
+                if cursor > 0 {
+                    let offset = self.byte_offset(cursor - 1);
+                    self.strval.remove(offset);
+                    self.cursor = (cursor - 1) as u16;
+                }
+            }
+            Action::DeleteChar => {
+                let cursor = self.cursor();
+                let offset = self.byte_offset(cursor);
+                if offset < self.strval.len() {
+                    self.strval.remove(offset);
+                    self.cursor = cursor as u16;
+                }
+            }
+            Action::LeftChar => {
+                let cursor = self.cursor();
+                if cursor > 0 {
+                    self.cursor = (cursor - 1) as u16;
+                }
+            }
+            Action::LeftWord => {
+                if let Some(cursor) = self.left_word_offset() {
+                    self.cursor = cursor as u16;
+                }
+            }
+            Action::RightWord => {
+                if let Some(cursor) = self.right_word_offset() {
+                    self.cursor = cursor as u16;
+                }
+            }
+            Action::DelBackWord => {
+                let cur_cursor = self.cursor();
+                if let Some(cursor) = self.left_word_offset() {
+                    self.kill(self.byte_offset(cursor)..self.byte_offset(cur_cursor));
+                    self.cursor = cursor as u16;
+                }
+            }
+            Action::KillToEnd => {
+                self.kill(self.byte_offset(self.cursor())..self.strval.len());
+            }
+            Action::KillToStart => {
+                self.kill(0..self.byte_offset(self.cursor()));
+                self.cursor = 0;
+            }
+            Action::Yank => {
+                let text = self.kill_ring.clone();
+                return self.insert(&text);
+            }
+            Action::GotoLineStart => {
+                self.cursor = 0;
+            }
+            Action::GotoLineEnd => {
+                self.cursor = self.strval.chars().count() as u16;
+            }
+            Action::RightChar => {
+                self.cursor = (self.cursor() + 1) as u16;
+                self.cursor = self.cursor() as u16;
+            }
+            Action::Complete => match self.tab_action {
+                TabAction::Complete => return self.complete(),
+                TabAction::InsertSpaces(width) => {
+                    let width = std::cmp::max(width, 1);
+                    let column: u16 =
+                        self.strval.chars().take(self.cursor()).map(char_width).sum();
+                    let nr_spaces = width - column % width;
+                    return self.insert(&" ".repeat(nr_spaces as usize));
+                }
+                TabAction::InsertTab => {
+                    return self.insert("\t");
+                }
+            },
+            Action::HistoryPrev => {
+                let index = self.history_index.unwrap_or(self.history.len());
+                if index == 0 {
+                    return false;
+                }
+                if self.history_index.is_none() {
+                    self.history_draft = self.strval.clone();
+                }
+                self.history_index = Some(index - 1);
+                self.recall(self.history[index - 1].clone());
+            }
+            Action::HistoryNext => match self.history_index {
+                None => return false,
+                Some(index) if index + 1 < self.history.len() => {
+                    self.history_index = Some(index + 1);
+                    self.recall(self.history[index + 1].clone());
+                }
+                Some(_) => {
+                    self.history_index = None;
+                    let draft = std::mem::take(&mut self.history_draft);
+                    self.recall(draft);
+                }
+            },
+            Action::TransposeChars => {
+                // At the end of the line, the last two characters are swapped.
+                let nr_chars = self.strval.chars().count();
+                let cursor = std::cmp::min(self.cursor(), nr_chars.saturating_sub(1));
+                if cursor == 0 {
+                    return false;
+                }
+                let c = self.strval.remove(self.byte_offset(cursor));
+                let offset = self.byte_offset(cursor - 1);
+                self.strval.insert(offset, c);
+                self.cursor = (cursor + 1) as u16;
+            }
+            Action::SetMark => {
+                self.mark = Some(self.cursor());
+            }
+            Action::CutRegion | Action::CopyRegion => {
+                let region = match self.region() {
+                    Some(region) => region,
+                    None => return false,
+                };
+                let range = self.byte_offset(region.start)..self.byte_offset(region.end);
+                if let Action::CutRegion = action {
+                    self.kill(range);
+                    self.cursor = region.start as u16;
+                } else {
+                    self.kill_ring = self.strval[range].to_owned();
+                }
+                self.mark = None;
+            }
+            Action::HistorySearch => {
+                // Undoing the search restores the line it started from.
+                self.push_undo((self.strval.clone(), self.cursor));
+                self.redo_stack.clear();
+                self.search = Some(Search {
+                    query: String::new(),
+                    saved: (self.strval.clone(), self.cursor),
+                    index: None,
+                    failing: false,
+                });
+            }
+            Action::Undo | Action::Redo | Action::Cancel | Action::Accept => {}
+        }
+
+        true
+    }
+
+    /// Remove a byte range of the text, keeping it for `Yank` unless there
+    /// was nothing to remove.
+    fn kill(&mut self, range: std::ops::Range<usize>) {
+        let killed: String = self.strval.drain(range).collect();
+        if !killed.is_empty() {
+            self.kill_ring = killed;
+        }
+    }
+
+    /// Replace the text with a line from history, placing the cursor at the end.
+    fn recall(&mut self, line: String) {
+        self.strval = line;
+        self.cursor = self.strval.chars().count() as u16;
+    }
+
+    /// Inserts text at the cursor, refusing it if it would exceed the
+    /// maximum length.
+    fn insert(&mut self, text: &str) -> bool {
+        let cursor = self.cursor();
+        let offset = self.byte_offset(cursor);
+        let normalization = match self.normalization {
+            Some(normalization) => normalization,
+            None => {
+                let nr_chars = self.strval.chars().count() + text.chars().count();
+                if self.max_len.is_some_and(|max_len| nr_chars > max_len) {
+                    return false;
+                }
+                self.strval.insert_str(offset, text);
+                self.cursor = (cursor + text.chars().count()) as u16;
+                return true;
+            }
+        };
+
+        // Normalize together with the preceding text, so that a combining
+        // mark composes with the character before it.
+        let head = normalization.apply(&format!("{}{}", &self.strval[..offset], text));
+        let nr_head_chars = head.chars().count();
+        if let Some(max_len) = self.max_len {
+            if nr_head_chars + self.strval[offset..].chars().count() > max_len {
+                return false;
+            }
+        }
+
+        self.strval.replace_range(..offset, &head);
+        self.cursor = nr_head_chars as u16;
+        true
+    }
+
+    /// Start of the word before the cursor, skipping any spaces first
+    fn left_word_offset(&self) -> Option<usize> {
+        let v = self.char_classes();
+        let mut cursor = self.cursor();
+        if cursor == 0 {
+            return None;
+        }
+
+        while cursor > 0 && v[cursor - 1] == CharClass::Space {
+            cursor -= 1;
+        }
+        if cursor > 0 {
+            let class = v[cursor - 1];
+            while cursor > 0 && v[cursor - 1] == class {
+                cursor -= 1;
+            }
+        }
+        Some(cursor)
+    }
+
+    /// Start of the word after the one at the cursor
+    fn right_word_offset(&self) -> Option<usize> {
+        let v = self.char_classes();
+        let mut cursor = self.cursor();
+        if cursor >= v.len() {
+            return None;
+        }
+
+        let class = v[cursor];
+        if class != CharClass::Space {
+            while cursor < v.len() && v[cursor] == class {
+                cursor += 1;
+            }
+        }
+        while cursor < v.len() && v[cursor] == CharClass::Space {
+            cursor += 1;
+        }
+        Some(cursor)
+    }
+}
+
+/// Kinds of characters, a run of one kind making up a word
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CharClass {
+    Space,
+    /// Letters, digits and underscores
+    Word,
+    Punctuation,
+    Han,
+    Hiragana,
+    Katakana,
+}
+
+impl CharClass {
+    /// The default classification
+    pub fn of(c: char) -> Self {
+        match c {
+            c if c.is_whitespace() => CharClass::Space,
+            '\u{3400}'..='\u{4dbf}'
+            | '\u{4e00}'..='\u{9fff}'
+            | '\u{f900}'..='\u{faff}'
+            | '\u{20000}'..='\u{2fa1f}' => CharClass::Han,
+            '\u{3040}'..='\u{309f}' => CharClass::Hiragana,
+            '\u{30a0}'..='\u{30ff}' | '\u{31f0}'..='\u{31ff}' => CharClass::Katakana,
+            c if c.is_alphanumeric() || c == '_' => CharClass::Word,
+            _ => CharClass::Punctuation,
+        }
+    }
+}
+
+fn char_width(c: char) -> u16 {
+    c.width().unwrap_or(1) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Renderer, TestBackend};
+    use crossterm::event::KeyModifiers;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn type_str(line: &mut ReadLine, s: &str) {
+        for c in s.chars() {
+            line.apply_action(&Action::InsertChar, key(KeyCode::Char(c)));
+        }
+    }
+
+    fn press(line: &mut ReadLine, action: Action) -> bool {
+        line.apply_action(&action, key(KeyCode::Null))
+    }
+
+    /// A line with the given text and the cursor `at` a character index
+    fn line_at(text: &str, at: usize) -> ReadLine {
+        let mut line = ReadLine::new();
+        line.set_value(text);
+        press(&mut line, Action::GotoLineStart);
+        for _ in 0..at {
+            press(&mut line, Action::RightChar);
+        }
+        line
+    }
+
+    fn renderer(width: u16) -> Renderer {
+        let mut renderer = Renderer::default();
+        renderer.probe_size(&TestBackend::new(width, 1)).unwrap();
+        renderer.begin().unwrap();
+        renderer
+    }
+
+    fn drawn(renderer: &Renderer) -> String {
+        renderer.render_to_text().trim_end().to_owned()
+    }
+
+    #[test]
+    fn mask_hides_text() {
+        let mut line = ReadLine::new();
+        line.set_mask(Some('*'));
+        type_str(&mut line, "pw日本");
+
+        let mut renderer = renderer(10);
+        let cursor = line.draw(0, 0, 10, &mut renderer, ReadLine::def_style_map());
+        assert_eq!(drawn(&renderer), "****");
+        assert_eq!(cursor, 4);
+        assert_eq!(line.strval(), "pw日本");
+    }
+
+    #[test]
+    fn mask_lays_out_by_mask_width() {
+        let mut line = ReadLine::new();
+        line.set_mask(Some('*'));
+        // Ten columns of text, but only five of mask, which fit unscrolled.
+        type_str(&mut line, "日日日日日");
+
+        let mut renderer = renderer(6);
+        let cursor = line.draw(0, 0, 6, &mut renderer, ReadLine::def_style_map());
+        assert_eq!(drawn(&renderer), "*****");
+        assert_eq!(cursor, 5);
+    }
+
+    #[test]
+    fn word_count_ignores_repeated_spaces() {
+        let mut line = ReadLine::new();
+        type_str(&mut line, "  one   two  three ");
+        assert_eq!(line.word_count(), 3);
+    }
+
+    #[test]
+    fn counter_is_drawn_right_aligned() {
+        let mut line = ReadLine::new();
+        line.set_counter_fn(Box::new(|s| format!("{}c", s.chars().count())));
+        type_str(&mut line, "abc");
+
+        let mut renderer = renderer(10);
+        line.draw(0, 0, 10, &mut renderer, ReadLine::def_style_map());
+        assert_eq!(drawn(&renderer), "abc     3c");
+    }
+
+    #[test]
+    fn max_len_refuses_insertion() {
+        let mut line = ReadLine::new();
+        line.set_max_len(Some(3));
+        // Counted in characters, not bytes
+        type_str(&mut line, "日本");
+        assert!(line.apply_action(&Action::InsertChar, key(KeyCode::Char('x'))));
+        assert!(!line.apply_action(&Action::InsertChar, key(KeyCode::Char('y'))));
+        assert_eq!(line.strval(), "日本x");
+        assert_eq!(line.get_cursor(), 5);
+        assert!(!line.insert_str("z"));
+        assert_eq!(line.strval(), "日本x");
+    }
+
+    #[test]
+    fn kill_to_end_from_middle() {
+        let mut line = line_at("hello world", 5);
+        press(&mut line, Action::KillToEnd);
+        assert_eq!(line.strval(), "hello");
+        assert_eq!(line.get_cursor(), 5);
+        assert_eq!(line.kill_ring, " world");
+    }
+
+    #[test]
+    fn kill_to_start_from_middle() {
+        let mut line = line_at("hello world", 5);
+        press(&mut line, Action::KillToStart);
+        assert_eq!(line.strval(), " world");
+        assert_eq!(line.get_cursor(), 0);
+        assert_eq!(line.kill_ring, "hello");
+    }
+
+    #[test]
+    fn tab_actions() {
+        let mut line = line_at("ab", 2);
+        assert!(!press(&mut line, Action::Complete));
+        assert_eq!(line.strval(), "ab");
+
+        line.set_tab_action(TabAction::InsertSpaces(4));
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "ab  ");
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "ab      ");
+
+        let mut line = line_at("ab", 2);
+        line.set_tab_action(TabAction::InsertTab);
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "ab\t");
+    }
+
+    #[test]
+    fn yank_at_line_start() {
+        let mut line = line_at("hello world", 6);
+        press(&mut line, Action::KillToEnd);
+        assert_eq!(line.strval(), "hello ");
+        press(&mut line, Action::GotoLineStart);
+        press(&mut line, Action::Yank);
+        assert_eq!(line.strval(), "worldhello ");
+        assert_eq!(line.get_cursor(), 5);
+    }
+
+    #[test]
+    fn empty_kill_keeps_kill_ring() {
+        let mut line = line_at("hello world", 6);
+        press(&mut line, Action::KillToEnd);
+        press(&mut line, Action::KillToEnd);
+        press(&mut line, Action::GotoLineStart);
+        press(&mut line, Action::KillToStart);
+        press(&mut line, Action::DelBackWord);
+        assert_eq!(line.kill_ring, "world");
+        press(&mut line, Action::Yank);
+        assert_eq!(line.strval(), "worldhello ");
+    }
+
+    #[test]
+    fn undo_typed_word_at_once() {
+        let mut line = ReadLine::new();
+        type_str(&mut line, "abc");
+        assert!(press(&mut line, Action::Undo));
+        assert_eq!(line.strval(), "");
+        assert_eq!(line.get_cursor(), 0);
+        assert!(!press(&mut line, Action::Undo));
+
+        assert!(press(&mut line, Action::Redo));
+        assert_eq!(line.strval(), "abc");
+        assert_eq!(line.get_cursor(), 3);
+    }
+
+    #[test]
+    fn undo_steps_through_edits() {
+        let mut line = ReadLine::new();
+        type_str(&mut line, "ab");
+        press(&mut line, Action::BackDeleteChar);
+        type_str(&mut line, "c");
+        press(&mut line, Action::Undo);
+        assert_eq!(line.strval(), "a");
+        press(&mut line, Action::Undo);
+        assert_eq!(line.strval(), "ab");
+    }
+
+    #[test]
+    fn normalize_inserted_text() {
+        let mut line = ReadLine::new();
+        line.set_normalization(Some(NormalizationForm::Nfc));
+        // "é" as 'e' followed by a combining acute accent
+        line.insert_str("cafe\u{301}");
+        assert_eq!(line.strval(), "caf\u{e9}");
+        assert_eq!(line.strval().chars().count(), 4);
+        assert_eq!(line.get_cursor(), 4);
+    }
+
+    #[test]
+    fn normalize_combining_key_with_preceding_char() {
+        let mut line = ReadLine::new();
+        line.set_normalization(Some(NormalizationForm::Nfc));
+        type_str(&mut line, "e\u{301}x");
+        assert_eq!(line.strval(), "\u{e9}x");
+
+        line.set_normalization(Some(NormalizationForm::Nfd));
+        line.insert_str("\u{e9}");
+        assert_eq!(line.strval(), "e\u{301}xe\u{301}");
+    }
+
+    fn completer() -> CompleteFn {
+        Box::new(|base| {
+            ["foo", "foobar", "fob", "bar"]
+                .iter()
+                .filter(|candidate| candidate.starts_with(base))
+                .map(|candidate| candidate.to_string())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn complete_cycles_inline_candidates() {
+        let mut line = ReadLine::new();
+        line.set_completer(Some(completer()));
+        type_str(&mut line, "fo");
+
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "foo");
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "foobar");
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "fob");
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "foo");
+        assert_eq!(line.get_cursor(), 3);
+
+        assert!(line.accept_completion());
+        assert!(!line.accept_completion());
+        assert_eq!(line.strval(), "foo");
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "foobar");
+    }
+
+    #[test]
+    fn undo_ends_completion() {
+        let mut line = ReadLine::new();
+        line.set_completer(Some(completer()));
+        type_str(&mut line, "fo");
+
+        press(&mut line, Action::Complete);
+        press(&mut line, Action::Undo);
+        assert_eq!(line.strval(), "fo");
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "foo");
+
+        press(&mut line, Action::Undo);
+        press(&mut line, Action::Redo);
+        assert_eq!(line.strval(), "foo");
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "foobar");
+    }
+
+    #[test]
+    fn complete_respects_max_len() {
+        let mut line = ReadLine::new();
+        line.set_completer(Some(completer()));
+        line.set_max_len(Some(4));
+        type_str(&mut line, "fo");
+
+        for expected in ["foo", "fob", "foo"] {
+            press(&mut line, Action::Complete);
+            assert_eq!(line.strval(), expected);
+        }
+
+        line.set_max_len(Some(2));
+        line.set_value("fo");
+        assert!(!press(&mut line, Action::Complete));
+        assert_eq!(line.strval(), "fo");
+    }
+
+    #[test]
+    fn complete_normalizes_candidates() {
+        let mut line = ReadLine::new();
+        line.set_completer(Some(Box::new(|_| vec!["cafe\u{301}".to_owned()])));
+        line.set_normalization(Some(NormalizationForm::Nfc));
+        type_str(&mut line, "caf");
+
+        press(&mut line, Action::Complete);
+        assert_eq!(line.strval(), "caf\u{e9}");
+    }
+
+    /// Cursor position at which moving right through a long line first
+    /// scrolls a field of `length` columns
+    fn first_scroll(scroll_margin: u16, length: u16) -> usize {
+        let mut line = line_at("abcdefghijklmnopqrstuvwxyz", 0);
+        line.set_scroll_margin(scroll_margin);
+        let mut renderer = renderer(length);
+        for cursor in 0..26 {
+            line.draw(0, 0, length, &mut renderer, ReadLine::def_style_map());
+            if line.h_scroll > 0 {
+                return cursor;
+            }
+            press(&mut line, Action::RightChar);
+        }
+        panic!("never scrolled");
+    }
+
+    #[test]
+    fn scroll_margin_scrolls_earlier() {
+        assert_eq!(first_scroll(0, 10), 10);
+        assert_eq!(first_scroll(2, 10), 8);
+        // Narrow fields shrink the margin, keeping the cursor visible.
+        assert_eq!(first_scroll(2, 3), 2);
+    }
+
+    #[test]
+    fn scroll_margin_keeps_text_left_of_cursor() {
+        let mut line = line_at("abcdefghijklmnopqrstuvwxyz", 26);
+        line.set_scroll_margin(2);
+        let mut renderer = renderer(10);
+        line.draw(0, 0, 10, &mut renderer, ReadLine::def_style_map());
+        for _ in 0..7 {
+            press(&mut line, Action::LeftChar);
+        }
+        renderer.begin().unwrap();
+        let cursor = line.draw(0, 0, 10, &mut renderer, ReadLine::def_style_map());
+        assert_eq!(drawn(&renderer), "rstuvwxyz");
+        assert_eq!(cursor, 2);
+    }
+
+    #[test]
+    fn inserting_many_chars_edits_in_place() {
+        let mut line = ReadLine::new();
+        let mut reallocations = 0;
+        let mut ptr = line.strval().as_ptr();
+        for i in 0..10_000 {
+            let c = if i % 2 == 0 { 'a' } else { 'é' };
+            line.apply_action(&Action::InsertChar, key(KeyCode::Char(c)));
+            if line.strval().as_ptr() != ptr {
+                reallocations += 1;
+                ptr = line.strval().as_ptr();
+            }
+        }
+        assert_eq!(line.strval().chars().count(), 10_000);
+        assert!(reallocations < 100, "{} reallocations", reallocations);
+
+        let mut line = line_at("abé", 2);
+        type_str(&mut line, "xy");
+        assert_eq!(line.strval(), "abxyé");
+        press(&mut line, Action::BackDeleteChar);
+        press(&mut line, Action::DeleteChar);
+        assert_eq!(line.strval(), "abx");
+    }
+
+    #[test]
+    fn history_walks_up_and_down() {
+        let mut line = ReadLine::new();
+        for entry in ["one", "two", "three"] {
+            line.push_history(entry);
+        }
+        type_str(&mut line, "draft");
+
+        let up = || key(KeyCode::Up);
+        let down = || key(KeyCode::Down);
+        line.handle_key(up());
+        assert_eq!((line.strval(), line.cursor()), ("three", 5));
+        line.handle_key(up());
+        line.handle_key(up());
+        assert_eq!((line.strval(), line.cursor()), ("one", 3));
+        line.handle_key(up());
+        assert_eq!(line.strval(), "one");
+
+        line.handle_key(down());
+        assert_eq!(line.strval(), "two");
+        line.handle_key(down());
+        line.handle_key(down());
+        assert_eq!((line.strval(), line.cursor()), ("draft", 5));
+        line.handle_key(down());
+        assert_eq!(line.strval(), "draft");
+    }
+
+    #[test]
+    fn history_search_finds_match() {
+        let mut line = ReadLine::new();
+        line.push_history("git commit");
+        line.push_history("git push");
+        type_str(&mut line, "draft");
+        let ctrl_r = KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL);
+
+        line.handle_key(ctrl_r);
+        type_str(&mut line, "comm");
+        assert_eq!((line.strval(), line.cursor()), ("git commit", 4));
+        let mut renderer = renderer(40);
+        line.draw(0, 0, 40, &mut renderer, ReadLine::def_style_map());
+        assert!(drawn(&renderer).contains("comm"));
+        assert!(drawn(&renderer).contains("git commit"));
+
+        line.handle_key(key(KeyCode::Esc));
+        assert_eq!(line.strval(), "draft");
+
+        line.handle_key(ctrl_r);
+        type_str(&mut line, "comm");
+        assert_eq!(
+            line.handle_key(key(KeyCode::Enter)),
+            Some(Outcome::Submitted("git commit".to_owned()))
+        );
+    }
+
+    #[test]
+    fn placeholder_only_when_empty() {
+        let mut line = ReadLine::new();
+        line.set_placeholder(Some("search…".to_owned()));
+
+        let mut renderer = renderer(20);
+        let cursor = line.draw(0, 0, 20, &mut renderer, ReadLine::def_style_map());
+        assert_eq!(drawn(&renderer), "search…");
+        assert_eq!(cursor, 0);
+        assert_eq!(line.strval(), "");
+
+        type_str(&mut line, "x");
+        renderer.begin().unwrap();
+        line.draw(0, 0, 20, &mut renderer, ReadLine::def_style_map());
+        assert_eq!(drawn(&renderer), "x");
+    }
+
+    #[test]
+    fn validator_rejects_letter() {
+        let mut line = ReadLine::new();
+        line.set_validator(Some(Box::new(|s: &str| {
+            if s.chars().all(|c| c.is_ascii_digit()) {
+                Ok(())
+            } else {
+                Err("digits only".to_owned())
+            }
+        })));
+        type_str(&mut line, "80");
+        assert!(line.is_valid());
+        assert_eq!(line.error(), None);
+
+        type_str(&mut line, "x");
+        assert!(!line.is_valid());
+        assert_eq!(line.error(), Some("digits only"));
+        assert_eq!(line.handle_key(key(KeyCode::Enter)), Some(Outcome::Refused));
+
+        press(&mut line, Action::BackDeleteChar);
+        assert!(line.is_valid());
+    }
+
+    fn word_stops(text: &str, start: usize, action: Action) -> Vec<usize> {
+        let mut line = line_at(text, start);
+        let mut stops = vec![];
+        loop {
+            press(&mut line, action);
+            if stops.last().unwrap_or(&start) == &line.cursor() {
+                return stops;
+            }
+            stops.push(line.cursor());
+        }
+    }
+
+    #[test]
+    fn word_motions_split_punctuation() {
+        assert_eq!(word_stops("foo.bar baz", 0, Action::RightWord), vec![3, 4, 8, 11]);
+        assert_eq!(word_stops("foo.bar baz", 11, Action::LeftWord), vec![8, 4, 3, 0]);
+
+        let mut line = line_at("foo.bar baz", 11);
+        press(&mut line, Action::DelBackWord);
+        assert_eq!(line.strval(), "foo.bar ");
+        press(&mut line, Action::DelBackWord);
+        assert_eq!(line.strval(), "foo.");
+    }
+
+    #[test]
+    fn word_motions_split_cjk_scripts() {
+        let text = "日本語をカタカナで 漢字";
+        assert_eq!(word_stops(text, 0, Action::RightWord), vec![3, 4, 8, 10, 12]);
+        assert_eq!(word_stops(text, 12, Action::LeftWord), vec![10, 8, 4, 3, 0]);
+    }
+
+    #[test]
+    fn prompt_offsets_cursor() {
+        let mut line = ReadLine::new();
+        line.set_prompt("> ");
+        type_str(&mut line, "ab");
+
+        let mut renderer = renderer(20);
+        let cursor = line.draw(3, 0, 10, &mut renderer, ReadLine::def_style_map());
+        assert_eq!(drawn(&renderer), "   > ab");
+        assert_eq!(cursor, 7);
+
+        // Eight columns are left for the text once the prompt is drawn.
+        type_str(&mut line, "cdefghij");
+        renderer.begin().unwrap();
+        let cursor = line.draw(3, 0, 10, &mut renderer, ReadLine::def_style_map());
+        assert!(drawn(&renderer).starts_with("   > "));
+        assert!(cursor < 13, "{}", cursor);
+        assert!(drawn(&renderer).ends_with('j'));
+    }
+
+    #[test]
+    fn insert_str_is_one_edit() {
+        let mut line = line_at("[]", 1);
+        assert!(line.insert_str("hello world"));
+        assert_eq!(line.strval(), "[hello world]");
+        assert_eq!(line.cursor(), 12);
+
+        press(&mut line, Action::Undo);
+        assert_eq!((line.strval(), line.cursor()), ("[]", 1));
+    }
+
+    #[test]
+    fn set_value_moves_cursor_to_end() {
+        let mut line = ReadLine::new();
+        type_str(&mut line, "a long line that scrolls");
+        let mut renderer = renderer(8);
+        line.draw(0, 0, 8, &mut renderer, ReadLine::def_style_map());
+        assert!(line.h_scroll > 0);
+
+        line.set_value("日本語");
+        assert_eq!((line.strval(), line.cursor()), ("日本語", 3));
+        assert_eq!(line.h_scroll, 0);
+        type_str(&mut line, "!");
+        assert_eq!(line.strval(), "日本語!");
+
+        line.clear();
+        assert_eq!((line.strval(), line.cursor()), ("", 0));
+        press(&mut line, Action::LeftChar);
+        assert_eq!(line.cursor(), 0);
+    }
+
+    #[test]
+    fn transpose_chars() {
+        let mut line = line_at("ba", 1);
+        let ctrl_t = KeyEvent::new(KeyCode::Char('t'), KeyModifiers::CONTROL);
+        line.handle_key(ctrl_t);
+        assert_eq!((line.strval(), line.cursor()), ("ab", 2));
+
+        // At the end the last two characters swap.
+        line.handle_key(ctrl_t);
+        assert_eq!((line.strval(), line.cursor()), ("ba", 2));
+
+        let mut line = line_at("x", 1);
+        press(&mut line, Action::TransposeChars);
+        assert_eq!((line.strval(), line.cursor()), ("x", 1));
+    }
+
+    #[test]
+    fn cut_marked_region() {
+        let mut line = line_at("abcde", 1);
+        line.handle_key(KeyEvent::new(KeyCode::Char(' '), KeyModifiers::CONTROL));
+        press(&mut line, Action::RightChar);
+        press(&mut line, Action::RightChar);
+        assert_eq!(line.region(), Some(1..3));
+
+        press(&mut line, Action::CutRegion);
+        assert_eq!((line.strval(), line.cursor()), ("ade", 1));
+        assert_eq!(line.region(), None);
+        press(&mut line, Action::GotoLineEnd);
+        press(&mut line, Action::Yank);
+        assert_eq!(line.strval(), "adebc");
+
+        // Copying leaves the text in place.
+        let mut line = line_at("abcde", 4);
+        press(&mut line, Action::SetMark);
+        press(&mut line, Action::LeftChar);
+        press(&mut line, Action::LeftChar);
+        press(&mut line, Action::CopyRegion);
+        press(&mut line, Action::GotoLineStart);
+        press(&mut line, Action::Yank);
+        assert_eq!(line.strval(), "cdabcde");
+    }
+
+    #[test]
+    fn enter_submits_line() {
+        let mut line = ReadLine::new();
+        type_str(&mut line, "ls -l");
+        assert_eq!(
+            line.handle_key(key(KeyCode::Enter)),
+            Some(Outcome::Submitted("ls -l".to_owned()))
+        );
+        assert_eq!(line.handle_key(key(KeyCode::Esc)), Some(Outcome::Cancelled));
+    }
+
+    #[test]
+    fn enter_accepts_previewed_completion() {
+        let mut line = ReadLine::new();
+        line.set_completer(Some(completer()));
+        type_str(&mut line, "fo");
+        line.handle_key(key(KeyCode::Tab));
+        assert_eq!(line.strval(), "foo");
+
+        assert_eq!(line.handle_key(key(KeyCode::Enter)), Some(Outcome::Edited));
+        assert_eq!(line.strval(), "foo");
+        assert_eq!(
+            line.handle_key(key(KeyCode::Enter)),
+            Some(Outcome::Submitted("foo".to_owned()))
+        );
+    }
+
+    #[test]
+    fn custom_word_boundary_stops_at_slash() {
+        let mut line = line_at("usr/local_bin", 13);
+        line.set_word_boundary(Some(Box::new(|c| match c {
+            '/' => CharClass::Space,
+            c => CharClass::of(c),
+        })));
+        let ctrl_left = KeyEvent::new(KeyCode::Left, KeyModifiers::CONTROL);
+        line.handle_key(ctrl_left);
+        assert_eq!(line.cursor(), 4);
+        line.handle_key(ctrl_left);
+        assert_eq!(line.cursor(), 0);
+
+        press(&mut line, Action::GotoLineEnd);
+        press(&mut line, Action::DelBackWord);
+        assert_eq!(line.strval(), "usr/");
+
+        // By default '/' is punctuation, a word of its own.
+        let mut line = line_at("usr/local", 9);
+        line.handle_key(ctrl_left);
+        assert_eq!(line.cursor(), 4);
+        line.handle_key(ctrl_left);
+        assert_eq!(line.cursor(), 3);
+    }
+}
@@ -1,20 +1,26 @@
 //! Double buffering terminal renderer
 
+use super::backend::Backend;
 use ansi_term::{ANSIString, ANSIStrings};
 use crossterm::{
-    cursor,
-    cursor::MoveTo,
-    event::Event,
-    style,
-    style::{Color, Colors, ContentStyle, Print, SetAttribute, SetAttributes, SetBackgroundColor, SetForegroundColor, Attribute},
+    event::{
+        DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+        Event,
+    },
+    style::{Attribute, Color, Colors, ContentStyle},
     terminal,
-    terminal::{Clear, ClearType},
-    terminal::{EnterAlternateScreen, LeaveAlternateScreen},
-    QueueableCommand,
+    terminal::{Clear, ClearType, DisableLineWrap, EnableLineWrap},
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen, SetTitle},
+    Command,
 };
-use std::io::Write;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use unicode_width::UnicodeWidthChar;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -26,11 +32,17 @@ pub enum Error {
 
     #[error("Io error; {0}")]
     IoError(#[from] std::io::Error),
+
+    #[error("Position out of bounds; ({0}, {1})")]
+    OutOfBounds(u16, u16),
 }
 
 #[derive(Clone, Eq, PartialEq)]
 struct CellContent {
     c: char,
+    /// Zero-width characters following `c`, such as combining accents,
+    /// which the terminal draws over it
+    combining: String,
     width: u8,
     style: ContentStyle,
 }
@@ -39,10 +51,38 @@ impl CellContent {
     fn new(c: char, style: ContentStyle) -> Self {
         CellContent {
             c,
+            combining: String::new(),
             width: c.width().unwrap_or(1) as u8,
             style,
         }
     }
+
+    /// A grapheme cluster, such as an emoji sequence joined with ZWJ, in a
+    /// single cell of the cluster's display width
+    fn cluster(cluster: &str, style: ContentStyle) -> Self {
+        let mut chars = cluster.chars();
+        let c = chars.next().unwrap_or(' ');
+        CellContent {
+            c,
+            combining: chars.as_str().to_owned(),
+            width: cluster_width(cluster) as u8,
+            style,
+        }
+    }
+
+    fn push_to(&self, text: &mut String) {
+        text.push(self.c);
+        text.push_str(&self.combining);
+    }
+
+    fn print(&self, tty: &mut impl Backend) -> Result<(), Error> {
+        if self.combining.is_empty() {
+            return tty.print(self.c.encode_utf8(&mut [0; 4]));
+        }
+        let mut cluster = String::new();
+        self.push_to(&mut cluster);
+        tty.print(&cluster)
+    }
 }
 
 #[derive(Clone, Eq, PartialEq)]
@@ -59,7 +99,8 @@ impl Cell {
 
 #[derive(Clone, Eq, PartialEq)]
 struct VirtualBuffer {
-    cells: Vec<Vec<Cell>>,
+    /// `width * height` cells, row by row
+    cells: Vec<Cell>,
     cursor: Option<(u16, u16)>,
     width: u16,
     height: u16,
@@ -70,7 +111,10 @@ impl VirtualBuffer {
         Self {
             width,
             height,
-            cells: vec![vec![Cell::new(' ', ContentStyle::default())]],
+            cells: vec![
+                Cell::new(' ', ContentStyle::default());
+                width as usize * height as usize
+            ],
             cursor: None,
         }
     }
@@ -80,23 +124,50 @@ impl VirtualBuffer {
             return;
         }
 
-        self.cells.resize(height as usize, vec![]);
-
-        for i in 0..height as usize {
-            self.cells[i].resize(width as usize, Cell::new(' ', ContentStyle::default()));
+        let mut cells =
+            vec![Cell::new(' ', ContentStyle::default()); width as usize * height as usize];
+        let kept_width = std::cmp::min(width, self.width) as usize;
+        for y in 0..std::cmp::min(height, self.height) {
+            let start = y as usize * width as usize;
+            cells[start..start + kept_width].clone_from_slice(&self.row(y)[..kept_width]);
         }
 
+        self.cells = cells;
         self.width = width;
         self.height = height;
     }
 
-    fn clear(&mut self) {
+    fn row(&self, y: u16) -> &[Cell] {
+        let start = y as usize * self.width as usize;
+        &self.cells[start..start + self.width as usize]
+    }
+
+    fn row_mut(&mut self, y: u16) -> &mut [Cell] {
+        let start = y as usize * self.width as usize;
+        &mut self.cells[start..start + self.width as usize]
+    }
+
+    fn get(&self, x: u16, y: u16) -> Option<&Cell> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.row(y).get(x as usize)
+    }
+
+    /// The character and style at a position, a space for the second column
+    /// of a wide character.
+    fn cell(&self, x: u16, y: u16) -> Option<(char, ContentStyle)> {
+        match self.get(x, y)? {
+            Cell::Content(content) => Some((content.c, content.style)),
+            Cell::WideExtension => Some((' ', ContentStyle::default())),
+        }
+    }
+
+    fn clear(&mut self, c: char, style: ContentStyle) {
         self.cursor = None;
 
-        for y in 0..self.height as usize {
-            for x in 0..self.width as usize {
-                self.cells[y][x] = Cell::new(' ', ContentStyle::default());
-            }
+        for cell in &mut self.cells {
+            *cell = Cell::new(c, style);
         }
     }
 }
@@ -108,12 +179,184 @@ pub enum Config {
     BottomScreen(NrLines, Option<(u16, u16)>),
 }
 
+/// How `end()` paints the last column, where most terminals auto-wrap the
+/// cursor and may scroll the screen after writing the bottom-right cell.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LastColumn {
+    /// Print the last column like any other, trusting the terminal.
+    Plain,
+    /// Disable auto-wrap (DECAWM) during the paint and restore it after.
+    DisableAutoWrap,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CursorShape {
+    DefaultUserShape,
+    BlinkingBlock,
+    SteadyBlock,
+    BlinkingUnderscore,
+    SteadyUnderscore,
+    BlinkingBar,
+    SteadyBar,
+}
+
+/// Sets the cursor shape with DECSCUSR
+struct SetCursorStyle(CursorShape);
+
+impl Command for SetCursorStyle {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        let n = match self.0 {
+            CursorShape::DefaultUserShape => 0,
+            CursorShape::BlinkingBlock => 1,
+            CursorShape::SteadyBlock => 2,
+            CursorShape::BlinkingUnderscore => 3,
+            CursorShape::SteadyUnderscore => 4,
+            CursorShape::BlinkingBar => 5,
+            CursorShape::SteadyBar => 6,
+        };
+        write!(f, "\x1b[{} q", n)
+    }
+
+    #[cfg(windows)]
+    fn execute_winapi(&self) -> crossterm::Result<()> {
+        Ok(())
+    }
+}
+
+/// Sets the lines that scroll (DECSTBM), inclusive and 0-based, or resets
+/// them to the whole screen
+struct SetScrollRegion(Option<(u16, u16)>);
+
+impl Command for SetScrollRegion {
+    fn write_ansi(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        match self.0 {
+            Some((top, bottom)) => write!(f, "\x1b[{};{}r", top + 1, bottom + 1),
+            None => write!(f, "\x1b[r"),
+        }
+    }
+
+    #[cfg(windows)]
+    fn execute_winapi(&self) -> crossterm::Result<()> {
+        Ok(())
+    }
+}
+
+/// Which colors the terminal can show. Colors it can't are replaced with
+/// the nearest it can in `end()`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColorMode {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+    /// No colors at all, only attributes
+    None,
+}
+
+impl ColorMode {
+    /// Guess from the environment: `$NO_COLOR` turns colors off, and
+    /// `$COLORTERM` or `$TERM` tell how many the terminal supports.
+    pub fn detect() -> Self {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        if !var("NO_COLOR").is_empty() {
+            return ColorMode::None;
+        }
+        let colorterm = var("COLORTERM");
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorMode::TrueColor
+        } else if var("TERM").contains("256color") {
+            ColorMode::Ansi256
+        } else {
+            ColorMode::Ansi16
+        }
+    }
+
+    /// The color to show in place of `color`, if any
+    pub fn adapt_color(self, color: Color) -> Option<Color> {
+        match (self, color) {
+            (ColorMode::None, _) => None,
+            (ColorMode::TrueColor, color) | (_, color @ Color::Reset) => Some(color),
+            (ColorMode::Ansi256, Color::Rgb { r, g, b }) => {
+                Some(Color::AnsiValue(ansi256_index((r, g, b))))
+            }
+            (ColorMode::Ansi256, color) => Some(color),
+            (ColorMode::Ansi16, Color::Rgb { .. } | Color::AnsiValue(_)) => {
+                let rgb = color_rgb(color)?;
+                Some(NAMED_COLORS[nearest_index(rgb, 0..16) as usize])
+            }
+            (ColorMode::Ansi16, color) => Some(color),
+        }
+    }
+
+    fn adapt(self, style: ContentStyle) -> ContentStyle {
+        ContentStyle {
+            foreground_color: style.foreground_color.and_then(|c| self.adapt_color(c)),
+            background_color: style.background_color.and_then(|c| self.adapt_color(c)),
+            underline_color: style.underline_color.and_then(|c| self.adapt_color(c)),
+            attributes: style.attributes,
+        }
+    }
+}
+
+/// Hint derived from recent frame times, for apps that want to skip
+/// optional drawing when rendering is slow.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Quality {
+    Full,
+    /// Frames take longer than the budget on average
+    Reduced,
+}
+
+/// Number of recent frames averaged by `avg_frame_ms()`
+const FRAME_TIMES_WINDOW: usize = 30;
+
+/// Horizontal position of text within a field
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
 pub struct Renderer {
     term_size: (u16, u16),
     config: Config,
     next: VirtualBuffer,
     prev: VirtualBuffer,
     full_refresh: bool,
+    last_column: LastColumn,
+    /// Set asynchronously when the terminal was resized, checked by `begin()`.
+    resized: Option<Arc<AtomicBool>>,
+    clear_scrollback_on_exit: bool,
+    /// Durations of the most recent `end()` calls
+    frame_times: VecDeque<Duration>,
+    frame_budget: Option<Duration>,
+    /// Region `(x, y, width, height)` outside of which draws are dropped
+    clip: Option<(u16, u16, u16, u16)>,
+    cursor_shape: CursorShape,
+    /// The shape last sent to the terminal
+    prev_cursor_shape: CursorShape,
+    title: Option<String>,
+    /// The title last sent to the terminal
+    prev_title: Option<String>,
+    mouse_capture: bool,
+    /// Whether mouse reporting is currently enabled in the terminal
+    mouse_captured: bool,
+    bracketed_paste: bool,
+    /// Whether bracketed paste is currently enabled in the terminal
+    bracketed_paste_enabled: bool,
+    /// Lines requested by `set_bottom_height`, if more than the minimum
+    bottom_height: u16,
+    color_mode: ColorMode,
+    /// What `begin()` fills the frame with
+    clear_char: char,
+    clear_style: ContentStyle,
+    /// Columns between tab stops
+    tab_width: u8,
+    scroll_region: bool,
+    /// The scroll region currently set in the terminal
+    scroll_region_set: Option<(u16, u16)>,
+    /// Cursors stacked by `save_cursor`
+    saved_cursors: Vec<Option<(u16, u16)>>,
 }
 
 impl Default for Renderer {
@@ -124,29 +367,299 @@ impl Default for Renderer {
             next: VirtualBuffer::new(1, 1),
             prev: VirtualBuffer::new(1, 1),
             full_refresh: true,
+            last_column: LastColumn::DisableAutoWrap,
+            resized: None,
+            clear_scrollback_on_exit: false,
+            frame_times: VecDeque::with_capacity(FRAME_TIMES_WINDOW),
+            frame_budget: None,
+            clip: None,
+            cursor_shape: CursorShape::DefaultUserShape,
+            prev_cursor_shape: CursorShape::DefaultUserShape,
+            title: None,
+            prev_title: None,
+            mouse_capture: false,
+            mouse_captured: false,
+            bracketed_paste: false,
+            bracketed_paste_enabled: false,
+            bottom_height: 0,
+            color_mode: ColorMode::detect(),
+            clear_char: ' ',
+            clear_style: ContentStyle::default(),
+            tab_width: 8,
+            scroll_region: false,
+            scroll_region_set: None,
+            saved_cursors: vec![],
         }
     }
 }
 
 impl VirtualBuffer {
+    /// Place a character at `(x, y)`, returning its width or `None` if it
+    /// doesn't fit. A zero-width character instead joins the character
+    /// before `x`, and is dropped if there is none.
     fn putchar(&mut self, x: u16, y: u16, c: char, style: ContentStyle) -> Option<u16> {
-        let c = CellContent::new(c, style);
+        self.put(x, y, CellContent::new(c, style))
+    }
+
+    fn put(&mut self, x: u16, y: u16, c: CellContent) -> Option<u16> {
         if c.width as usize + x as usize > self.width as usize {
             return None;
         }
-        if y as usize >= self.cells.len() {
+        if y >= self.height {
             return None;
         }
+        if c.width == 0 {
+            self.attach(x, y, &c);
+            return Some(0);
+        }
 
         let width = c.width;
-        self.cells[y as usize][x as usize] = Cell::Content(c);
+        self.clear_wide_partners(x, y, std::cmp::max(width as u16, 1));
+        let row = self.row_mut(y);
+        row[x as usize] = Cell::Content(c);
 
-        for x in x + 1..x + width as u16 {
-            self.cells[y as usize][x as usize] = Cell::WideExtension;
+        for cell in &mut row[x as usize + 1..x as usize + width as usize] {
+            *cell = Cell::WideExtension;
         }
 
         Some(width as u16)
     }
+
+    fn attach(&mut self, x: u16, y: u16, c: &CellContent) {
+        let row = self.row_mut(y);
+        let mut head = x as usize;
+        while head > 0 {
+            head -= 1;
+            if let Cell::Content(content) = &mut row[head] {
+                c.push_to(&mut content.combining);
+                return;
+            }
+        }
+    }
+
+    /// Before overwriting `width` cells at `(x, y)`, blank the parts of wide
+    /// characters that the write would leave stranded on either side.
+    fn clear_wide_partners(&mut self, x: u16, y: u16, width: u16) {
+        let row = self.row_mut(y);
+        let start = x as usize;
+        let end = std::cmp::min(start + width as usize, row.len());
+
+        if let Cell::WideExtension = row[start] {
+            let mut head = start;
+            while head > 0 && row[head] == Cell::WideExtension {
+                head -= 1;
+            }
+            let style = match &row[head] {
+                Cell::Content(content) => content.style,
+                Cell::WideExtension => ContentStyle::default(),
+            };
+            for cell in &mut row[head..start] {
+                *cell = Cell::new(' ', style);
+            }
+        }
+
+        if end < row.len() && row[end] == Cell::WideExtension {
+            let style = match &row[end - 1] {
+                Cell::Content(content) => content.style,
+                Cell::WideExtension => ContentStyle::default(),
+            };
+            let mut i = end;
+            while i < row.len() && row[i] == Cell::WideExtension {
+                row[i] = Cell::new(' ', style);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// Read-only copy of a frame, taken with `Renderer::snapshot`
+#[derive(Clone, Eq, PartialEq)]
+pub struct Frame {
+    buffer: VirtualBuffer,
+}
+
+impl Frame {
+    /// Width and height
+    pub fn dimensions(&self) -> (u16, u16) {
+        (self.buffer.width, self.buffer.height)
+    }
+
+    /// The character and style at a position. The second column of a wide
+    /// character reads as a space.
+    pub fn cell(&self, x: u16, y: u16) -> Option<(char, ContentStyle)> {
+        self.buffer.cell(x, y)
+    }
+
+    /// The whole grapheme cluster at a position, including combining
+    /// characters.
+    pub fn cell_text(&self, x: u16, y: u16) -> Option<String> {
+        match self.buffer.get(x, y)? {
+            Cell::Content(content) => {
+                let mut text = String::new();
+                content.push_to(&mut text);
+                Some(text)
+            }
+            Cell::WideExtension => Some(" ".to_owned()),
+        }
+    }
+
+    /// Where the cursor was set to be shown
+    pub fn cursor(&self) -> Option<(u16, u16)> {
+        self.buffer.cursor
+    }
+}
+
+/// Styled characters laid out by a widget of its own, composited into the
+/// frame with `Renderer::blit`. A wide character covers the cell after it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CellGrid {
+    width: u16,
+    height: u16,
+    /// `width * height` cells, row by row
+    cells: Vec<(char, ContentStyle)>,
+}
+
+impl CellGrid {
+    /// A grid of spaces in the default style
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![(' ', ContentStyle::default()); width as usize * height as usize],
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn get(&self, x: u16, y: u16) -> Option<(char, ContentStyle)> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.cells[y as usize * self.width as usize + x as usize])
+    }
+
+    /// Set a cell, ignoring positions outside of the grid.
+    pub fn set(&mut self, x: u16, y: u16, c: char, style: ContentStyle) {
+        if x < self.width && y < self.height {
+            self.cells[y as usize * self.width as usize + x as usize] = (c, style);
+        }
+    }
+}
+
+/// Offscreen buffer of any size, drawn into once and shown a slice at a
+/// time with `Renderer::blit_viewport`, e.g. for a scrolling log pane.
+pub struct Viewport {
+    buffer: VirtualBuffer,
+}
+
+impl Viewport {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            buffer: VirtualBuffer::new(width, height),
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.buffer.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.buffer.height
+    }
+
+    /// Change the size, keeping what was drawn where it still fits.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.buffer.resize(width, height);
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear(' ', ContentStyle::default());
+    }
+
+    pub fn draw_str(&mut self, mut x: u16, y: u16, s: &str, style: ContentStyle) -> u16 {
+        let start_x = x;
+        for cluster in s.graphemes(true) {
+            match self.buffer.put(x, y, CellContent::cluster(cluster, style)) {
+                Some(w) => x += w,
+                None => break,
+            }
+        }
+        x - start_x
+    }
+
+    pub fn draw_char(&mut self, x: u16, y: u16, c: char, style: ContentStyle) -> u16 {
+        self.buffer.putchar(x, y, c, style).unwrap_or_default()
+    }
+}
+
+/// Draws text one chunk after the other from a position, obtained with
+/// `Renderer::pen`.
+pub struct Pen<'a> {
+    renderer: &'a mut Renderer,
+    /// Column that `newline` returns to
+    start_x: u16,
+    x: u16,
+    y: u16,
+    style: ContentStyle,
+    /// Positions stacked by `push_pos`
+    saved: Vec<(u16, u16)>,
+}
+
+impl<'a> Pen<'a> {
+    /// Style of the following writes
+    pub fn style(&mut self, style: ContentStyle) -> &mut Self {
+        self.style = style;
+        self
+    }
+
+    /// Draw text at the current position and move past it.
+    pub fn write(&mut self, s: &str) -> &mut Self {
+        let width = self.renderer.draw_str(self.x, self.y, s, self.style);
+        self.x = self.x.saturating_add(width);
+        self
+    }
+
+    /// Move to the start column of the next row.
+    pub fn newline(&mut self) -> &mut Self {
+        self.x = self.start_x;
+        self.y = self.y.saturating_add(1);
+        self
+    }
+
+    pub fn pos(&self) -> (u16, u16) {
+        (self.x, self.y)
+    }
+
+    pub fn move_to(&mut self, x: u16, y: u16) -> &mut Self {
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    /// Remember the current position, to return to with `pop_pos`.
+    pub fn push_pos(&mut self) -> &mut Self {
+        self.saved.push((self.x, self.y));
+        self
+    }
+
+    /// Return to the position last pushed. Does nothing if none was.
+    pub fn pop_pos(&mut self) -> &mut Self {
+        if let Some((x, y)) = self.saved.pop() {
+            self.x = x;
+            self.y = y;
+        }
+        self
+    }
+
+    pub fn renderer(&mut self) -> &mut Renderer {
+        self.renderer
+    }
 }
 
 pub trait Drawable<'a> {
@@ -162,6 +675,20 @@ where
     }
 }
 
+/// Spans drawn one after another
+impl<'a, S> Drawable<'a> for &[(S, ContentStyle)]
+where
+    S: AsRef<str> + 'a,
+{
+    fn draw(&self, renderer: &mut Renderer, x: u16, y: u16) -> u16 {
+        let mut width = 0;
+        for (s, style) in self.iter() {
+            width += renderer.draw_str(x.saturating_add(width), y, s.as_ref(), *style);
+        }
+        width
+    }
+}
+
 impl<'a, S> Drawable<'a> for (S, Colors)
 where
     S: AsRef<str> + 'a,
@@ -178,20 +705,22 @@ where
     }
 }
 
-impl<'a, 'b> Drawable<'a> for &'b str
+impl<'a> Drawable<'a> for &str
 {
     fn draw(&self, renderer: &mut Renderer, x: u16, y: u16) -> u16 {
         renderer.draw_str(x, y, self, ContentStyle::default())
     }
 }
 
-impl<'a, 'b> Drawable<'a> for &'b String
+impl<'a> Drawable<'a> for &String
 {
     fn draw(&self, renderer: &mut Renderer, x: u16, y: u16) -> u16 {
         renderer.draw_str(x, y, self.as_str(), ContentStyle::default())
     }
 }
 
+/// For a styled character use `draw_char`; `(char, ContentStyle)` can't be
+/// drawable next to `(S, ContentStyle)` for any `S: AsRef<str>`.
 impl<'a> Drawable<'a> for char
 {
     fn draw(&self, renderer: &mut Renderer, x: u16, y: u16) -> u16 {
@@ -199,7 +728,7 @@ impl<'a> Drawable<'a> for char
     }
 }
 
-impl<'a, 'b> Drawable<'a> for &'b ANSIString<'a> {
+impl<'a> Drawable<'a> for &ANSIString<'a> {
     fn draw(&self, renderer: &mut Renderer, x: u16, y: u16) -> u16 {
         renderer.draw_ansi(x, y, self)
     }
@@ -217,6 +746,42 @@ impl<'a> Drawable<'a> for ANSIStrings<'a> {
     }
 }
 
+/// Keeps the terminal set up by `Renderer::enter` until dropped
+pub struct TerminalGuard<'a, W: Backend> {
+    renderer: &'a mut Renderer,
+    tty: &'a mut W,
+}
+
+impl<'a, W: Backend> TerminalGuard<'a, W> {
+    pub fn tty(&mut self) -> &mut W {
+        self.tty
+    }
+
+    pub fn end(&mut self) -> Result<(), Error> {
+        self.renderer.end(self.tty)
+    }
+}
+
+impl<'a, W: Backend> std::ops::Deref for TerminalGuard<'a, W> {
+    type Target = Renderer;
+
+    fn deref(&self) -> &Renderer {
+        self.renderer
+    }
+}
+
+impl<'a, W: Backend> std::ops::DerefMut for TerminalGuard<'a, W> {
+    fn deref_mut(&mut self) -> &mut Renderer {
+        self.renderer
+    }
+}
+
+impl<'a, W: Backend> Drop for TerminalGuard<'a, W> {
+    fn drop(&mut self) {
+        let _ = self.renderer.term_off(self.tty);
+    }
+}
+
 impl Renderer {
     pub fn bottom_screen(mut self, min_nr_lines: u16) -> Self {
         self.set_bottom_screen(min_nr_lines);
@@ -228,115 +793,455 @@ impl Renderer {
         self
     }
 
-    pub fn width(&self) -> u16 {
-        self.term_size.0
+    pub fn set_last_column(&mut self, last_column: LastColumn) -> &mut Self {
+        self.last_column = last_column;
+        self
     }
 
-    pub fn height(&self) -> u16 {
-        match &self.config {
-            Config::FullScreen => self.term_size.1,
-            Config::BottomScreen(lines, _) => std::cmp::min(*lines, self.term_size.1),
-        }
+    /// Use a flag that is raised when the terminal is resized, so that
+    /// `begin()` picks up the new size even when events are not being read.
+    pub fn set_resize_flag(&mut self, flag: Arc<AtomicBool>) -> &mut Self {
+        self.resized = Some(flag);
+        self
     }
 
-    pub fn term_on(&mut self, tty: &mut impl Write) -> Result<(), Error> {
-        terminal::enable_raw_mode()?;
-        tty.queue(cursor::Hide)?;
+    /// Register a SIGWINCH handler that raises the resize flag.
+    #[cfg(all(unix, feature = "signal-hook"))]
+    pub fn watch_sigwinch(&mut self) -> Result<&mut Self, Error> {
+        let flag = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGWINCH, flag.clone())?;
+        Ok(self.set_resize_flag(flag))
+    }
 
-        let (x, y) = crossterm::terminal::size()?;
-        self.on_resize(x, y);
+    /// Clear the terminal's scrollback (`\e[3J`) in `term_off`.
+    pub fn set_clear_scrollback_on_exit(&mut self, enabled: bool) -> &mut Self {
+        self.clear_scrollback_on_exit = enabled;
+        self
+    }
 
-        match &mut self.config {
-            Config::FullScreen => {
-                tty.queue(EnterAlternateScreen)?;
-            }
-            Config::BottomScreen(lines, pos) => {
-                // Make space for new lines
-                let l = std::cmp::min(*lines, self.term_size.1);
-                let position = crossterm::cursor::position()?;
-                let y = std::cmp::min(self.term_size.1 - l, position.1);
-                for yl in 0..l {
-                    if yl + 1 >= l && y != position.1 {
-                        break;
-                    }
+    /// Character and style that each frame starts out filled with, for a
+    /// themed background without repainting every cell.
+    pub fn set_background_style(&mut self, c: char, style: ContentStyle) -> &mut Self {
+        self.clear_char = c;
+        self.clear_style = style;
+        self
+    }
 
-                    tty.queue(style::ResetColor)?;
-                    tty.queue(Print("\n"))?;
-                    tty.queue(Clear(ClearType::UntilNewLine))?;
-                }
-                *pos = Some(position);
-            }
-        };
+    /// Columns between the tab stops of `draw_str_tabbed` and
+    /// `draw_text_block`, at least 1.
+    pub fn set_tab_width(&mut self, width: u8) -> &mut Self {
+        self.tab_width = std::cmp::max(width, 1);
+        self
+    }
 
-        tty.flush()?;
+    /// Confine scrolling to the bottom screen's lines, so that output there
+    /// can't scroll the shell history above. Applied from `term_on` or the
+    /// next `end()`, and has no effect in full screen.
+    pub fn set_scroll_region(&mut self, enabled: bool) -> &mut Self {
+        self.scroll_region = enabled;
+        self
+    }
 
-        Ok(())
+    /// Ask the terminal to report mouse events, from `term_on` or the next
+    /// `end()`. They then arrive as `Event::Mouse` for the host to handle.
+    pub fn set_mouse_capture(&mut self, enabled: bool) -> &mut Self {
+        self.mouse_capture = enabled;
+        self
     }
 
-    pub fn term_off(&mut self, tty: &mut impl Write) -> Result<(), Error> {
-        match self.config {
-            Config::FullScreen => {
-                tty.queue(LeaveAlternateScreen)?;
-            }
-            Config::BottomScreen(lines, position) => {
-                // Clear lines
-                let position = position.clone().take().unwrap_or((0, 0));
-                let l = std::cmp::min(lines, self.term_size.1);
-                let y = std::cmp::min(self.term_size.1 - l, position.1);
-                tty.queue(MoveTo(position.0, y))?;
-                for yl in 0..l {
-                    tty.queue(style::ResetColor)?;
-                    tty.queue(Clear(ClearType::UntilNewLine))?;
-                    if yl + 1 >= l && y != position.1 {
-                        break;
-                    }
-                    tty.queue(Print("\n"))?;
-                }
-                tty.queue(MoveTo(position.0, y))?;
-            }
-        };
+    /// Override the color support detected from the environment.
+    pub fn set_color_mode(&mut self, color_mode: ColorMode) -> &mut Self {
+        self.color_mode = color_mode;
+        self
+    }
 
-        tty.queue(cursor::Show)?;
-        tty.flush()?;
-        terminal::disable_raw_mode()?;
+    /// Have the terminal deliver pasted text as a single `Event::Paste`,
+    /// from `term_on` or the next `end()`.
+    pub fn set_bracketed_paste(&mut self, enabled: bool) -> &mut Self {
+        self.bracketed_paste = enabled;
+        self
+    }
 
-        Ok(())
+    /// Frame time above which `quality_hint()` suggests reduced quality.
+    pub fn set_frame_budget(&mut self, budget: Option<Duration>) -> &mut Self {
+        self.frame_budget = budget;
+        self
     }
 
-    fn on_resize(&mut self, x: u16, y: u16) {
-        let prev_term_size = self.term_size;
-        self.term_size = (x, y);
+    /// Rolling average of the time spent in `end()`, in milliseconds.
+    pub fn avg_frame_ms(&self) -> f64 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        let total: Duration = self.frame_times.iter().sum();
+        total.as_secs_f64() * 1000.0 / self.frame_times.len() as f64
+    }
 
-        let y = match &mut self.config {
-            Config::FullScreen => y,
-            Config::BottomScreen(lines, position) => {
-                match position {
-                    None => {}
-                    Some(position) => {
-                        let l = std::cmp::min(*lines, prev_term_size.1);
-                        let y = std::cmp::min(prev_term_size.1 - l, position.1);
-                        if y != position.1 {
-                            position.1 += self.term_size.1;
-                            position.1 -= prev_term_size.1;
-                        }
-                    }
-                }
+    pub fn quality_hint(&self) -> Quality {
+        match self.frame_budget {
+            Some(budget) if self.avg_frame_ms() > budget.as_secs_f64() * 1000.0 => Quality::Reduced,
+            _ => Quality::Full,
+        }
+    }
 
-                std::cmp::min(*lines, y)
-            }
-        };
+    fn record_frame_time(&mut self, duration: Duration) {
+        if self.frame_times.len() >= FRAME_TIMES_WINDOW {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(duration);
+    }
+
+    pub fn width(&self) -> u16 {
+        self.term_size.0
+    }
+
+    pub fn height(&self) -> u16 {
+        self.lines_within(self.term_size.1)
+    }
+
+    /// The region that can be drawn to, as `(x, y, width, height)`. Draw
+    /// coordinates start at the top-left of the used lines in any mode, so
+    /// the origin is always `(0, 0)`.
+    pub fn area(&self) -> (u16, u16, u16, u16) {
+        (0, 0, self.width(), self.height())
+    }
+
+    /// Same as `height()`
+    pub fn rows(&self) -> u16 {
+        self.height()
+    }
+
+    /// Same as `width()`
+    pub fn cols(&self) -> u16 {
+        self.width()
+    }
+
+    /// Number of lines used on a terminal of the given height
+    fn lines_within(&self, term_height: u16) -> u16 {
+        match &self.config {
+            Config::FullScreen => term_height,
+            Config::BottomScreen(lines, _) => {
+                let lines = std::cmp::max(*lines, self.bottom_height);
+                std::cmp::min(lines, term_height)
+            }
+        }
+    }
+
+    /// The terminal row where the bottom screen starts
+    fn bottom_top(&self) -> u16 {
+        match &self.config {
+            Config::FullScreen => 0,
+            Config::BottomScreen(_, position) => {
+                let position = position.unwrap_or((0, 0));
+                let l = self.height();
+                std::cmp::min(self.term_size.1.saturating_sub(l), position.1)
+            }
+        }
+    }
+
+    /// Grow the bottom screen beyond its minimum number of lines, scrolling
+    /// the terminal to make room as needed, or shrink it back. Has no effect
+    /// in fullscreen mode.
+    pub fn set_bottom_height(&mut self, height: u16, tty: &mut impl Backend) -> Result<(), Error> {
+        if let Config::FullScreen = self.config {
+            return Ok(());
+        }
+
+        let top = self.bottom_top();
+        let prev_l = self.height();
+        self.bottom_height = height;
+        let l = self.height();
+
+        if let Config::BottomScreen(_, Some(position)) = &mut self.config {
+            if l > prev_l {
+                // A newline at the bottom of the scroll region would only
+                // scroll the strip. `end()` sets the region again to the new
+                // size.
+                if self.scroll_region_set.is_some() {
+                    tty.command(SetScrollRegion(None))?;
+                    self.scroll_region_set = None;
+                }
+                tty.move_to(0, (top + prev_l).saturating_sub(1))?;
+                for _ in prev_l..l {
+                    tty.reset_color()?;
+                    tty.print("\n")?;
+                    tty.command(Clear(ClearType::UntilNewLine))?;
+                }
+            } else if l < prev_l {
+                // Stay where the lines were pushed up to, if they were.
+                position.1 = std::cmp::min(position.1, top);
+                for y in top + l..top + prev_l {
+                    tty.move_to(0, y)?;
+                    tty.reset_color()?;
+                    tty.command(Clear(ClearType::UntilNewLine))?;
+                }
+            }
+        }
+
+        let (x, y) = self.term_size;
+        self.on_resize(x, y);
+        Ok(())
+    }
+
+    pub fn term_on(&mut self, tty: &mut impl Backend) -> Result<(), Error> {
+        tty.enable_raw_mode()?;
+        tty.hide_cursor()?;
+
+        let (x, y) = tty.size()?;
+        self.on_resize(x, y);
+        self.enter_screen(tty)?;
+        self.update_modes(tty)?;
+
+        tty.flush()?;
+
+        Ok(())
+    }
+
+    /// Read the terminal size and resize the buffers to it, without raw
+    /// mode or entering the screen, e.g. to check that the terminal is big
+    /// enough before `term_on`. Returns the terminal size.
+    pub fn probe_size(&mut self, tty: &impl Backend) -> Result<(u16, u16), Error> {
+        let (x, y) = tty.size()?;
+        self.on_resize(x, y);
+        Ok((x, y))
+    }
+
+    /// Bring the terminal's input modes in line with the settings.
+    fn update_modes(&mut self, tty: &mut impl Backend) -> Result<(), Error> {
+        if self.mouse_capture != self.mouse_captured {
+            if self.mouse_capture {
+                tty.command(EnableMouseCapture)?;
+            } else {
+                tty.command(DisableMouseCapture)?;
+            }
+            self.mouse_captured = self.mouse_capture;
+        }
+
+        if self.bracketed_paste != self.bracketed_paste_enabled {
+            if self.bracketed_paste {
+                tty.command(EnableBracketedPaste)?;
+            } else {
+                tty.command(DisableBracketedPaste)?;
+            }
+            self.bracketed_paste_enabled = self.bracketed_paste;
+        }
+
+        let scroll_region = match self.config {
+            Config::BottomScreen(..) if self.scroll_region => {
+                let top = self.bottom_top();
+                Some((top, top + self.height().saturating_sub(1)))
+            }
+            _ => None,
+        };
+        if scroll_region != self.scroll_region_set {
+            tty.command(SetScrollRegion(scroll_region))?;
+            self.scroll_region_set = scroll_region;
+        }
+
+        Ok(())
+    }
+
+    fn enter_screen(&mut self, tty: &mut impl Backend) -> Result<(), Error> {
+        let l = self.height();
+        match &mut self.config {
+            Config::FullScreen => {
+                tty.command(EnterAlternateScreen)?;
+            }
+            Config::BottomScreen(_, pos) => {
+                // The position query bypasses any buffering, so let what was
+                // queued so far take effect first.
+                tty.flush()?;
+                let position = tty.cursor_position()?;
+
+                // Make space for new lines, starting at the cursor's line.
+                // Moving down onto the last one scrolls the terminal only as
+                // far as the lines don't fit below the cursor.
+                tty.reset_color()?;
+                for yl in 0..l {
+                    if yl > 0 {
+                        tty.print("\n")?;
+                    }
+                    tty.command(Clear(ClearType::UntilNewLine))?;
+                }
+                *pos = Some(position);
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Like `term_on`, but returns a guard that calls `term_off` when
+    /// dropped, restoring the terminal even if the host panics.
+    pub fn enter<'a, W: Backend>(
+        &'a mut self,
+        tty: &'a mut W,
+    ) -> Result<TerminalGuard<'a, W>, Error> {
+        self.term_on(tty)?;
+        Ok(TerminalGuard {
+            renderer: self,
+            tty,
+        })
+    }
+
+    pub fn term_off(&mut self, tty: &mut impl Backend) -> Result<(), Error> {
+        self.leave_screen(tty)?;
+
+        if self.mouse_captured {
+            tty.command(DisableMouseCapture)?;
+            self.mouse_captured = false;
+        }
+
+        if self.bracketed_paste_enabled {
+            tty.command(DisableBracketedPaste)?;
+            self.bracketed_paste_enabled = false;
+        }
+
+        if self.prev_cursor_shape != CursorShape::DefaultUserShape {
+            tty.command(SetCursorStyle(CursorShape::DefaultUserShape))?;
+            self.prev_cursor_shape = CursorShape::DefaultUserShape;
+        }
+
+        if self.clear_scrollback_on_exit {
+            tty.command(Clear(ClearType::Purge))?;
+        }
+
+        tty.show_cursor()?;
+        tty.flush()?;
+        tty.disable_raw_mode()?;
+
+        Ok(())
+    }
+
+    fn leave_screen(&mut self, tty: &mut impl Backend) -> Result<(), Error> {
+        if self.scroll_region_set.is_some() {
+            tty.command(SetScrollRegion(None))?;
+            self.scroll_region_set = None;
+        }
+
+        match self.config {
+            Config::FullScreen => {
+                tty.command(LeaveAlternateScreen)?;
+            }
+            Config::BottomScreen(_, position) => {
+                // Clear lines in place, without printing newlines that could
+                // scroll. Whether or not `term_on` had to push the lines up,
+                // the line the cursor was on is now the top one.
+                let position = position.unwrap_or((0, 0));
+                let y = self.bottom_top();
+                tty.reset_color()?;
+                for row in y..y + self.height() {
+                    tty.move_to(0, row)?;
+                    tty.command(Clear(ClearType::CurrentLine))?;
+                }
+                tty.move_to(position.0, y)?;
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Change between fullscreen and bottom-screen modes while the terminal
+    /// is on, tearing down the current mode and setting up the new one.
+    pub fn switch_config(&mut self, config: Config, tty: &mut impl Backend) -> Result<(), Error> {
+        self.leave_screen(tty)?;
+        self.config = config;
+        self.bottom_height = 0;
+
+        let (x, y) = tty.size()?;
+        self.on_resize(x, y);
+        self.enter_screen(tty)?;
+
+        tty.flush()?;
+
+        Ok(())
+    }
 
-        self.next.resize(x, y);
-        self.prev.resize(x, y);
+    fn on_resize(&mut self, x: u16, y: u16) {
+        let prev_term_size = self.term_size;
+        self.term_size = (x, y);
+
+        let l = self.lines_within(prev_term_size.1);
+        let height = self.lines_within(y);
+        match &mut self.config {
+            Config::FullScreen => {}
+            Config::BottomScreen(_, position) => {
+                match position {
+                    None => {}
+                    Some(position) => {
+                        let y = std::cmp::min(prev_term_size.1.saturating_sub(l), position.1);
+                        if y != position.1 {
+                            // Follow the lines as the terminal grows or shrinks
+                            // beneath them, stopping at the top.
+                            position.1 = position
+                                .1
+                                .saturating_add(self.term_size.1)
+                                .saturating_sub(prev_term_size.1);
+                        }
+                    }
+                }
+            }
+        };
+
+        self.next.resize(x, height);
+        self.prev.resize(x, height);
         self.full_refresh = true;
     }
 
     pub fn event(&mut self, event: &Event) {
-        match event {
-            Event::Resize(x, y) => {
-                self.on_resize(*x, *y);
+        if let Event::Resize(x, y) = event {
+            self.on_resize(*x, *y);
+        }
+    }
+
+    /// Constrain subsequent draws to the given rectangle.
+    pub fn with_clip(&mut self, x: u16, y: u16, width: u16, height: u16) -> &mut Self {
+        self.clip = Some((x, y, width, height));
+        self
+    }
+
+    pub fn clear_clip(&mut self) -> &mut Self {
+        self.clip = None;
+        self
+    }
+
+    fn putchar(&mut self, x: u16, y: u16, c: char, style: ContentStyle) -> Option<u16> {
+        self.put(x, y, CellContent::new(c, style))
+    }
+
+    fn put_cluster(&mut self, x: u16, y: u16, cluster: &str, style: ContentStyle) -> Option<u16> {
+        self.put(x, y, CellContent::cluster(cluster, style))
+    }
+
+    /// Like `VirtualBuffer::put`, but honoring the clip rectangle. Cells
+    /// left of the clip are skipped over so drawing can continue into it.
+    fn put(&mut self, x: u16, y: u16, content: CellContent) -> Option<u16> {
+        if let Some((clip_x, clip_y, clip_width, clip_height)) = self.clip {
+            let width = content.width as u32;
+            if (y as u32) < clip_y as u32 || y as u32 >= clip_y as u32 + clip_height as u32 {
+                return None;
+            }
+            if x as u32 + width > clip_x as u32 + clip_width as u32 {
+                return None;
             }
-            _ => {}
+            // A zero-width character at the clip's edge would join one
+            // outside of it.
+            if x < clip_x || (width == 0 && x == clip_x) {
+                return Some(width as u16);
+            }
+        }
+
+        self.next.put(x, y, content)
+    }
+
+    /// Start drawing sequential text at `(x, y)`, in the default style.
+    pub fn pen(&mut self, x: u16, y: u16) -> Pen<'_> {
+        Pen {
+            renderer: self,
+            start_x: x,
+            x,
+            y,
+            style: ContentStyle::default(),
+            saved: vec![],
         }
     }
 
@@ -344,25 +1249,379 @@ impl Renderer {
         drawable.draw(self, x, y)
     }
 
-    pub fn draw_str(&mut self, mut x: u16, y: u16, s: &str, style: ContentStyle) -> u16 {
+    /// Draw text from `(x, y)` until it reaches the edge. Returns the width
+    /// drawn, which is 0 when the position is off the screen.
+    pub fn draw_str(&mut self, x: u16, y: u16, s: &str, style: ContentStyle) -> u16 {
+        self.draw_str_checked(x, y, s, style).0
+    }
+
+    /// Like `draw_str`, but failing if the position is off the screen, to
+    /// catch layout bugs.
+    pub fn try_draw_str(
+        &mut self,
+        x: u16,
+        y: u16,
+        s: &str,
+        style: ContentStyle,
+    ) -> Result<u16, Error> {
+        if x >= self.next.width || y >= self.next.height {
+            return Err(Error::OutOfBounds(x, y));
+        }
+        Ok(self.draw_str(x, y, s, style))
+    }
+
+    /// Draw text top to bottom, a grapheme cluster per row. Clusters that
+    /// don't fit are skipped, leaving their row to the next one. Returns the
+    /// number of rows drawn.
+    pub fn draw_str_vertical(&mut self, x: u16, y: u16, s: &str, style: ContentStyle) -> u16 {
+        let mut rows = 0;
+        for cluster in s.graphemes(true) {
+            // Combining marks with no character to join
+            if cluster_width(cluster) == 0 {
+                continue;
+            }
+            let row = y.saturating_add(rows);
+            if row >= self.next.height {
+                break;
+            }
+            if self.put_cluster(x, row, cluster, style).is_some() {
+                rows += 1;
+            }
+        }
+        rows
+    }
+
+    /// Draw text right-aligned so that it ends at column `x`, for
+    /// right-to-left scripts. The text is kept in logical order, and its start
+    /// is cut if it doesn't fit left of `x`. Returns the width drawn.
+    pub fn draw_str_rtl(&mut self, x: u16, y: u16, s: &str, style: ContentStyle) -> u16 {
+        let available = x.saturating_add(1);
+        let mut text = s;
+        // Counted in `usize`, as the text may be wider than any screen.
+        let mut width: usize = s.graphemes(true).map(|c| cluster_width(c) as usize).sum();
+        let mut clusters = s.grapheme_indices(true);
+        while width > available as usize {
+            match clusters.next() {
+                Some((index, cluster)) => {
+                    text = &s[index + cluster.len()..];
+                    width -= cluster_width(cluster) as usize;
+                }
+                None => break,
+            }
+        }
+        self.draw_str(available - width as u16, y, text, style)
+    }
+
+    /// Like `draw_str`, but spaces leave what is below them showing, as for
+    /// sprites drawn over other content.
+    pub fn draw_str_transparent(
+        &mut self,
+        mut x: u16,
+        y: u16,
+        s: &str,
+        style: ContentStyle,
+    ) -> u16 {
+        let start_x = x;
+        for cluster in s.graphemes(true) {
+            if cluster == " " {
+                if x >= self.next.width {
+                    break;
+                }
+                x += 1;
+                continue;
+            }
+            match self.put_cluster(x, y, cluster, style) {
+                Some(w) => x += w,
+                None => break,
+            }
+        }
+        x - start_x
+    }
+
+    /// Draw text with the colors swapped, as for a selection.
+    pub fn draw_str_reversed(&mut self, x: u16, y: u16, s: &str, style: ContentStyle) -> u16 {
+        let mut style = style;
+        style.attributes.set(Attribute::Reverse);
+        self.draw_str(x, y, s, style)
+    }
+
+    /// Like `draw_str`, but also returns whether the string was cut short
+    /// because the rest of it didn't fit.
+    pub fn draw_str_checked(
+        &mut self,
+        mut x: u16,
+        y: u16,
+        s: &str,
+        style: ContentStyle,
+    ) -> (u16, bool) {
         let start_x = x;
-        for c in s.chars() {
-            if let Some(w) = self.next.putchar(x, y, c, style) {
+        for cluster in s.graphemes(true) {
+            if let Some(w) = self.put_cluster(x, y, cluster, style) {
                 x += w;
             } else {
-                break;
+                return (x - start_x, true);
             }
         }
 
-        x - start_x
+        (x - start_x, false)
+    }
+
+    /// Draw a line of text, moving `\t` to the next tab stop counted from
+    /// `x` and skipping other control characters. Returns the width drawn.
+    pub fn draw_str_tabbed(&mut self, x: u16, y: u16, s: &str, style: ContentStyle) -> u16 {
+        self.draw_tabbed_line(x, y, s, style) - x
+    }
+
+    /// Draw a line expanding tabs, returning the column it ended at.
+    fn draw_tabbed_line(&mut self, x: u16, y: u16, line: &str, style: ContentStyle) -> u16 {
+        let tab_width = self.tab_width as u16;
+        let mut col = x;
+        for cluster in line.graphemes(true) {
+            if cluster == "\t" {
+                let stop = x.saturating_add(((col - x) / tab_width + 1) * tab_width);
+                while col < stop {
+                    match self.putchar(col, y, ' ', style) {
+                        Some(w) => col += w,
+                        None => return col,
+                    }
+                }
+            } else if !cluster.starts_with(char::is_control) {
+                match self.put_cluster(col, y, cluster, style) {
+                    Some(w) => col += w,
+                    None => return col,
+                }
+            }
+        }
+        col
     }
 
+    /// Draw text that may span lines: `\n` starts a new row back at `x`,
+    /// `\t` moves to the next tab stop counted from `x`, and other control
+    /// characters such as `\r` are skipped. Lines that don't fit are cut.
+    /// Returns the number of rows used.
+    pub fn draw_text_block(&mut self, x: u16, y: u16, text: &str, style: ContentStyle) -> u16 {
+        let mut rows = 0;
+        for line in text.split('\n') {
+            self.draw_tabbed_line(x, y.saturating_add(rows), line, style);
+            rows += 1;
+        }
+        rows
+    }
+
+    /// Place a single character. Returns its width, or 0 if it didn't fit.
     pub fn draw_char(&mut self, x: u16, y: u16, c: char, style: ContentStyle) -> u16 {
-        if let Some(w) = self.next.putchar(x, y, c, style) {
-            w
+        self.putchar(x, y, c, style).unwrap_or_default()
+    }
+
+    /// Fill a rectangle with a character, clamped to the buffer.
+    pub fn fill_rect(
+        &mut self,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        c: char,
+        style: ContentStyle,
+    ) {
+        let c_width = std::cmp::max(c.width().unwrap_or(1), 1) as u16;
+        let end_x = std::cmp::min(x.saturating_add(width), self.next.width);
+        let end_y = std::cmp::min(y.saturating_add(height), self.next.height);
+
+        for row in y..end_y {
+            let mut col = x;
+            while col + c_width <= end_x {
+                if self.putchar(col, row, c, style).is_none() {
+                    break;
+                }
+                col += c_width;
+            }
+        }
+    }
+
+    /// Set the background color of the cells in columns `from_x..to_x` of a
+    /// row, keeping what was drawn in them, e.g. to highlight a whole row
+    /// behind its text.
+    pub fn fill_line_bg(&mut self, y: u16, from_x: u16, to_x: u16, bg: Color) {
+        if y >= self.next.height {
+            return;
+        }
+        let (mut from_x, mut to_x) = (from_x, std::cmp::min(to_x, self.next.width));
+        if let Some((clip_x, clip_y, clip_width, clip_height)) = self.clip {
+            if y < clip_y || y as u32 >= clip_y as u32 + clip_height as u32 {
+                return;
+            }
+            from_x = std::cmp::max(from_x, clip_x);
+            to_x = std::cmp::min(to_x, clip_x.saturating_add(clip_width));
+        }
+
+        for cell in self.next.row_mut(y).iter_mut().take(to_x as usize).skip(from_x as usize) {
+            if let Cell::Content(content) = cell {
+                content.style.background_color = Some(bg);
+            }
+        }
+    }
+
+    /// Reset a rectangle to the background, leaving the rest of the frame as
+    /// drawn.
+    pub fn clear_rect(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        self.fill_rect(x, y, width, height, self.clear_char, self.clear_style);
+    }
+
+    /// Darken everything drawn so far, keeping `factor` of each color's
+    /// brightness, e.g. behind a dialog. Text in the terminal's default
+    /// color, which can't be scaled, gets the dim attribute instead.
+    pub fn dim_all(&mut self, factor: f32) {
+        let factor = if factor.is_nan() {
+            0.0
         } else {
-            0
+            factor.clamp(0.0, 1.0)
+        };
+        let scale = |color: Color| match color_rgb(color) {
+            Some((r, g, b)) => Color::Rgb {
+                r: (r as f32 * factor) as u8,
+                g: (g as f32 * factor) as u8,
+                b: (b as f32 * factor) as u8,
+            },
+            None => color,
+        };
+
+        for cell in self.next.cells.iter_mut() {
+            if let Cell::Content(content) = cell {
+                let style = &mut content.style;
+                match style.foreground_color {
+                    Some(color) if color != Color::Reset => {
+                        style.foreground_color = Some(scale(color));
+                    }
+                    _ => style.attributes.set(Attribute::Dim),
+                }
+                style.background_color = style.background_color.map(scale);
+            }
+        }
+    }
+
+    /// Position of a `width` x `height` rectangle centered on the screen, as
+    /// `(x, y, width, height)`. The size is clamped to the screen's.
+    pub fn centered_rect(&self, width: u16, height: u16) -> (u16, u16, u16, u16) {
+        let width = std::cmp::min(width, self.next.width);
+        let height = std::cmp::min(height, self.next.height);
+        let x = (self.next.width - width) / 2;
+        let y = (self.next.height - height) / 2;
+        (x, y, width, height)
+    }
+
+    /// Word-wrap text into `width` columns, starting at `(x, y)` and moving
+    /// down a row per line. Words longer than `width` are broken. Returns
+    /// the number of rows used.
+    pub fn draw_wrapped(
+        &mut self,
+        x: u16,
+        y: u16,
+        width: u16,
+        text: &str,
+        style: ContentStyle,
+    ) -> u16 {
+        if width == 0 {
+            return 0;
+        }
+
+        let mut lines = vec![];
+        for paragraph in text.split('\n') {
+            let mut line = String::new();
+            let mut line_width: u16 = 0;
+            for word in paragraph.split_whitespace() {
+                let word_width = str_width(word);
+                let joined_width = line_width.saturating_add(1).saturating_add(word_width);
+                if line_width > 0 && joined_width <= width {
+                    line.push(' ');
+                    line.push_str(word);
+                    line_width = joined_width;
+                    continue;
+                }
+                if line_width > 0 {
+                    lines.push(std::mem::take(&mut line));
+                    line_width = 0;
+                }
+                for cluster in word.graphemes(true) {
+                    let cluster_width = cluster_width(cluster);
+                    if line_width.saturating_add(cluster_width) > width && line_width > 0 {
+                        lines.push(std::mem::take(&mut line));
+                        line_width = 0;
+                    }
+                    line.push_str(cluster);
+                    line_width = line_width.saturating_add(cluster_width);
+                }
+            }
+            lines.push(line);
+        }
+
+        let rows = std::cmp::min(lines.len(), u16::MAX as usize) as u16;
+        for (row, line) in (0..rows).zip(&lines) {
+            self.draw_str(x, y.saturating_add(row), line, style);
+        }
+        rows
+    }
+
+    /// Draw text within `max_width` columns, ending it with `…` if it had to
+    /// be cut. Returns the width drawn.
+    pub fn draw_truncated(
+        &mut self,
+        x: u16,
+        y: u16,
+        max_width: u16,
+        text: &str,
+        style: ContentStyle,
+    ) -> u16 {
+        if str_width(text) <= max_width {
+            return self.draw_str(x, y, text, style);
+        }
+
+        const ELLIPSIS: char = '…';
+        let budget = match max_width.checked_sub(char_width(ELLIPSIS)) {
+            Some(budget) => budget,
+            None => return 0,
+        };
+        let mut truncated = String::new();
+        let mut width = 0;
+        for cluster in text.graphemes(true) {
+            // A wide character straddling the boundary is left out entirely.
+            if width + cluster_width(cluster) > budget {
+                break;
+            }
+            width += cluster_width(cluster);
+            truncated.push_str(cluster);
+        }
+        truncated.push(ELLIPSIS);
+
+        self.draw_str(x, y, &truncated, style)
+    }
+
+    /// Draw text aligned within a field of `width` columns starting at `x`,
+    /// clipping it at the field's edge. Returns the width drawn.
+    pub fn draw_aligned(
+        &mut self,
+        x: u16,
+        y: u16,
+        width: u16,
+        text: &str,
+        align: Align,
+        style: ContentStyle,
+    ) -> u16 {
+        let mut clipped = String::new();
+        let mut text_width = 0;
+        for cluster in text.graphemes(true) {
+            if text_width + cluster_width(cluster) > width {
+                break;
+            }
+            text_width += cluster_width(cluster);
+            clipped.push_str(cluster);
         }
+
+        let offset = match align {
+            Align::Left => 0,
+            Align::Center => (width - text_width) / 2,
+            Align::Right => width - text_width,
+        };
+        self.draw_str(x.saturating_add(offset), y, &clipped, style)
     }
 
     pub fn draw_ansi<'a>(&mut self, x: u16, y: u16, s: &ANSIString<'a>) -> u16 {
@@ -389,13 +1648,24 @@ impl Renderer {
             foreground_color: style.foreground.map(convert_color),
             underline_color: None,
             attributes: {
-                let attr = crossterm::style::Attributes::default();
-
+                let mut attr = crossterm::style::Attributes::default();
+                for (enabled, attribute) in [
+                    (style.is_bold, Attribute::Bold),
+                    (style.is_dimmed, Attribute::Dim),
+                    (style.is_italic, Attribute::Italic),
+                    (style.is_underline, Attribute::Underlined),
+                    (style.is_reverse, Attribute::Reverse),
+                    (style.is_strikethrough, Attribute::CrossedOut),
+                ] {
+                    if enabled {
+                        attr.set(attribute);
+                    }
+                }
                 attr
             },
         };
 
-        self.draw_str(x, y, &*s, content_style)
+        self.draw_str(x, y, s, content_style)
     }
 
     pub fn draw_ansis<'a>(&mut self, mut x: u16, y: u16, s: &ANSIStrings<'a>) -> u16 {
@@ -408,96 +1678,1537 @@ impl Renderer {
         x - start_x
     }
 
-    pub fn set_cursor(&mut self, info: Option<(u16, u16)>) {
-        self.next.cursor = info;
+    /// What has been drawn at a position in the current frame. The second
+    /// column of a wide character reads as a space.
+    pub fn cell_at(&self, x: u16, y: u16) -> Option<(char, ContentStyle)> {
+        self.next.cell(x, y)
     }
 
-    pub fn begin(&mut self) -> Result<(), Error> {
-        self.next.clear();
-        Ok(())
+    /// A copy of the frame drawn so far, e.g. for assertions in tests.
+    pub fn snapshot(&self) -> Frame {
+        Frame {
+            buffer: self.next.clone(),
+        }
     }
 
-    pub fn end(&mut self, tty: &mut impl Write) -> Result<(), Error> {
-        let top_left = match self.config {
-            Config::FullScreen => (0, 0),
-            Config::BottomScreen(lines, position) => {
-                let position = position.clone().take().unwrap_or((0, 0));
-                let l = std::cmp::min(lines, self.term_size.1);
-                let y = std::cmp::min(self.term_size.1 - l, position.1);
-                (0, y)
+    /// The frame drawn so far as text with ANSI escapes, reproducing it when
+    /// printed to a terminal, e.g. for snapshots in tests or bug reports.
+    pub fn render_to_ansi(&self) -> String {
+        let mut output: Vec<u8> = vec![];
+        let mut style = ContentStyle::default();
+        for y in 0..self.next.height {
+            for cell in self.next.row(y) {
+                if let Cell::Content(content) = cell {
+                    // Writing to a Vec can't fail.
+                    let _ = change_style(&mut output, &mut style, content.style);
+                    let _ = content.print(&mut output);
+                }
             }
+            // Don't let the colors of one row run into the next.
+            let _ = output.reset_attrs();
+            style = ContentStyle::default();
+            output.push(b'\n');
+        }
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    /// The characters of the frame drawn so far, a line per row with
+    /// trailing spaces trimmed.
+    pub fn render_to_text(&self) -> String {
+        let mut text = String::new();
+        for y in 0..self.next.height {
+            let mut line = String::new();
+            for cell in self.next.row(y) {
+                if let Cell::Content(content) = cell {
+                    content.push_to(&mut line);
+                }
+            }
+            text.push_str(line.trim_end_matches(' '));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// The frame drawn so far as an HTML `<pre>` block, with a `<span>` of
+    /// inline CSS for each run of styled cells.
+    pub fn render_to_html(&self) -> String {
+        let mut html = String::from("<pre>");
+        for y in 0..self.next.height {
+            let mut runs: Vec<(ContentStyle, String)> = vec![];
+            for cell in self.next.row(y) {
+                if let Cell::Content(content) = cell {
+                    match runs.last_mut() {
+                        Some((style, text)) if *style == content.style => {
+                            content.push_to(text);
+                        }
+                        _ => {
+                            let mut text = String::new();
+                            content.push_to(&mut text);
+                            runs.push((content.style, text));
+                        }
+                    }
+                }
+            }
+
+            for (style, text) in runs {
+                let css = css_style(style);
+                if !css.is_empty() {
+                    html.push_str(&format!("<span style=\"{}\">", css));
+                }
+                for c in text.chars() {
+                    match c {
+                        '&' => html.push_str("&amp;"),
+                        '<' => html.push_str("&lt;"),
+                        '>' => html.push_str("&gt;"),
+                        c => html.push(c),
+                    }
+                }
+                if !css.is_empty() {
+                    html.push_str("</span>");
+                }
+            }
+            html.push('\n');
+        }
+        html.push_str("</pre>");
+        html
+    }
+
+    /// Composite a grid with its top-left at `(dest_x, dest_y)`, clipped to
+    /// the screen. Wide characters cut off by the right edge are replaced
+    /// with a space.
+    pub fn blit(&mut self, grid: &CellGrid, dest_x: u16, dest_y: u16) {
+        for y in 0..grid.height {
+            let row = dest_y.saturating_add(y);
+            let mut x = 0;
+            while x < grid.width {
+                let (c, style) = grid.cells[y as usize * grid.width as usize + x as usize];
+                let col = dest_x.saturating_add(x);
+                let width = match self.putchar(col, row, c, style) {
+                    Some(width) => width,
+                    None => {
+                        self.putchar(col, row, ' ', style);
+                        1
+                    }
+                };
+                x = x.saturating_add(std::cmp::max(width, 1));
+            }
+        }
+    }
+
+    /// Copy `height` rows of a viewport, starting at its row `src_y`, to
+    /// `(dest_x, dest_y)`. Wide characters cut off by the right edge are
+    /// replaced with a space.
+    pub fn blit_viewport(
+        &mut self,
+        viewport: &Viewport,
+        src_y: u16,
+        dest_x: u16,
+        dest_y: u16,
+        height: u16,
+    ) {
+        let end_y = std::cmp::min(src_y.saturating_add(height), viewport.height());
+        for (row, src_row) in (src_y..end_y).enumerate() {
+            let y = dest_y.saturating_add(row as u16);
+            for (column, cell) in viewport.buffer.row(src_row).iter().enumerate() {
+                if let Cell::Content(content) = cell {
+                    let x = dest_x.saturating_add(column as u16);
+                    if self.put(x, y, content.clone()).is_none() {
+                        self.putchar(x, y, ' ', content.style);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Shape of the cursor from the next frame on, restored on `term_off`.
+    pub fn set_cursor_style(&mut self, shape: CursorShape) {
+        self.cursor_shape = shape;
+    }
+
+    /// Set the terminal window title, sent by `end()` when it changes.
+    pub fn set_title(&mut self, title: &str) {
+        self.title = Some(title.to_owned());
+    }
+
+    /// Repaint every line on the next `end()`, e.g. on Ctrl-L after the
+    /// screen got garbled by other output.
+    pub fn request_full_refresh(&mut self) {
+        self.full_refresh = true;
+    }
+
+    pub fn set_cursor(&mut self, info: Option<(u16, u16)>) {
+        self.next.cursor = info;
+    }
+
+    /// Push the cursor set for this frame, so a widget can place it and
+    /// then put it back with `restore_cursor`.
+    pub fn save_cursor(&mut self) {
+        self.saved_cursors.push(self.next.cursor);
+    }
+
+    /// Set the cursor back to the one last saved. Does nothing if none was.
+    pub fn restore_cursor(&mut self) {
+        if let Some(cursor) = self.saved_cursors.pop() {
+            self.next.cursor = cursor;
+        }
+    }
+
+    pub fn begin(&mut self) -> Result<(), Error> {
+        self.start_frame(|| Ok(terminal::size()?))
+    }
+
+    /// Like `begin`, but querying `tty` for the size after a resize, for
+    /// output to something other than the process's terminal.
+    pub fn begin_with(&mut self, tty: &impl Backend) -> Result<(), Error> {
+        self.start_frame(|| tty.size())
+    }
+
+    fn start_frame(
+        &mut self,
+        size: impl FnOnce() -> Result<(u16, u16), Error>,
+    ) -> Result<(), Error> {
+        let resized = match &self.resized {
+            Some(flag) => flag.swap(false, Ordering::SeqCst),
+            None => false,
         };
+        if resized {
+            let (x, y) = size()?;
+            self.on_resize(x, y);
+        }
+
+        self.next.clear(self.clear_char, self.clear_style);
+        Ok(())
+    }
+
+    pub fn end(&mut self, tty: &mut impl Backend) -> Result<(), Error> {
+        let start = Instant::now();
+        let top_left = (0, self.bottom_top());
+
+        self.update_modes(tty)?;
 
         let next = &self.next;
         let prev = &self.prev;
         let mut style = ContentStyle::default();
+        let color_mode = self.color_mode;
+        // Where printing leaves the terminal's cursor, in buffer coordinates
+        let mut cursor = None;
 
-        tty.queue(crossterm::style::ResetColor)?;
+        tty.reset_color()?;
+        if self.last_column == LastColumn::DisableAutoWrap {
+            tty.command(DisableLineWrap)?;
+        }
 
-        for y in 0..next.height as usize {
-            if next.cells[y] == prev.cells[y] && !self.full_refresh {
+        for y in 0..next.height {
+            let (next_row, prev_row) = (next.row(y), prev.row(y));
+            if next_row == prev_row && !self.full_refresh {
                 // Skip unmodified lines.
                 continue;
             }
 
-            tty.queue(MoveTo(0, top_left.1 + y as u16))?;
-
-            // TODO: find a subrange that is modified and keep the rest of the line as
-            // it is.
             for x in 0..next.width as usize {
-                match &next.cells[y][x] {
-                    Cell::Content(content) => {
-                        if style != content.style {
-                            if style.background_color != content.style.background_color {
-                                match content.style.background_color {
-                                    Some(x) => {
-                                        tty.queue(SetBackgroundColor(x))?;
-                                    }
-                                    None => {
-                                        tty.queue(SetBackgroundColor(Color::Reset))?;
-                                    }
-                                }
-                            }
-                            if style.foreground_color != content.style.foreground_color {
-                                match content.style.foreground_color {
-                                    Some(x) => {
-                                        tty.queue(SetForegroundColor(x))?;
-                                    }
-                                    None => {
-                                        tty.queue(SetForegroundColor(Color::Reset))?;
-                                    }
-                                }
-                            }
-                            if style.attributes != content.style.attributes {
-                                tty.queue(SetAttribute(Attribute::Reset))?;
-                                if let Some(x) = content.style.foreground_color {
-                                    tty.queue(SetForegroundColor(x))?;
-                                }
-                                if let Some(x) = content.style.background_color {
-                                    tty.queue(SetBackgroundColor(x))?;
-                                }
-                                tty.queue(SetAttributes(content.style.attributes))?;
-                            }
-                            style = content.style;
-                        }
-                        tty.queue(Print(content.c))?;
+                if next_row[x] == prev_row[x] && !self.full_refresh {
+                    continue;
+                }
+                if let Cell::Content(content) = &next_row[x] {
+                    if cursor != Some((x as u16, y)) {
+                        tty.move_to(x as u16, top_left.1 + y)?;
                     }
-                    _ => {}
+                    change_style(tty, &mut style, color_mode.adapt(content.style))?;
+                    content.print(tty)?;
+
+                    // After the last column the terminal may be holding a
+                    // pending wrap, so don't rely on where the cursor is.
+                    let after = x as u16 + content.width as u16;
+                    cursor = if after < next.width {
+                        Some((after, y))
+                    } else {
+                        None
+                    };
                 }
             }
         }
 
+        if self.last_column == LastColumn::DisableAutoWrap {
+            tty.command(EnableLineWrap)?;
+        }
+
+        if self.title != self.prev_title {
+            if let Some(title) = &self.title {
+                tty.command(SetTitle(title))?;
+            }
+            self.prev_title = self.title.clone();
+        }
+
+        if self.cursor_shape != self.prev_cursor_shape {
+            tty.command(SetCursorStyle(self.cursor_shape))?;
+            self.prev_cursor_shape = self.cursor_shape;
+        }
+
         if let Some(position) = next.cursor {
-            tty.queue(MoveTo(position.0 + top_left.0, position.1 + top_left.1))?;
-            tty.queue(cursor::Show)?;
+            tty.move_to(
+                position.0.saturating_add(top_left.0),
+                position.1.saturating_add(top_left.1),
+            )?;
+            tty.show_cursor()?;
         } else {
-            tty.queue(cursor::Hide)?;
+            tty.hide_cursor()?;
         }
 
         tty.flush()?;
         self.full_refresh = false;
 
         std::mem::swap(&mut self.next, &mut self.prev);
+        self.record_frame_time(start.elapsed());
         Ok(())
     }
 }
+
+/// Emit what it takes to go from drawing with `style` to drawing with
+/// `new_style`.
+fn change_style(
+    tty: &mut impl Backend,
+    style: &mut ContentStyle,
+    new_style: ContentStyle,
+) -> Result<(), Error> {
+    if *style == new_style {
+        return Ok(());
+    }
+
+    // Attributes can only be added, so removing any takes a reset, which
+    // also clears the colors.
+    let attributes = new_style.attributes;
+    if style.attributes & attributes != style.attributes {
+        tty.reset_attrs()?;
+        *style = ContentStyle::default();
+    }
+    if style.background_color != new_style.background_color {
+        tty.set_bg(new_style.background_color.unwrap_or(Color::Reset))?;
+    }
+    if style.foreground_color != new_style.foreground_color {
+        tty.set_fg(new_style.foreground_color.unwrap_or(Color::Reset))?;
+    }
+    if style.attributes != attributes {
+        tty.set_attrs(attributes)?;
+    }
+    *style = new_style;
+    Ok(())
+}
+
+/// Inline CSS for a style. Reversed cells without colors of their own are
+/// drawn black on white.
+fn css_style(style: ContentStyle) -> String {
+    let mut foreground = style.foreground_color.and_then(css_color);
+    let mut background = style.background_color.and_then(css_color);
+    if style.attributes.has(Attribute::Reverse) {
+        let reversed_background = foreground.unwrap_or_else(|| "#ffffff".to_owned());
+        foreground = Some(background.unwrap_or_else(|| "#000000".to_owned()));
+        background = Some(reversed_background);
+    }
+
+    let mut css = vec![];
+    if let Some(color) = foreground {
+        css.push(format!("color:{}", color));
+    }
+    if let Some(color) = background {
+        css.push(format!("background:{}", color));
+    }
+    if style.attributes.has(Attribute::Bold) {
+        css.push("font-weight:bold".to_owned());
+    }
+    if style.attributes.has(Attribute::Dim) {
+        css.push("opacity:0.5".to_owned());
+    }
+    if style.attributes.has(Attribute::Italic) {
+        css.push("font-style:italic".to_owned());
+    }
+    let mut decorations = vec![];
+    if style.attributes.has(Attribute::Underlined) {
+        decorations.push("underline");
+    }
+    if style.attributes.has(Attribute::CrossedOut) {
+        decorations.push("line-through");
+    }
+    if !decorations.is_empty() {
+        css.push(format!("text-decoration:{}", decorations.join(" ")));
+    }
+    css.join(";")
+}
+
+/// CSS for a terminal color
+fn css_color(color: Color) -> Option<String> {
+    let (r, g, b) = color_rgb(color)?;
+    Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
+/// The 16 basic colors in the xterm palette, in index order
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0x80, 0x00, 0x00),
+    (0x00, 0x80, 0x00),
+    (0x80, 0x80, 0x00),
+    (0x00, 0x00, 0x80),
+    (0x80, 0x00, 0x80),
+    (0x00, 0x80, 0x80),
+    (0xc0, 0xc0, 0xc0),
+    (0x80, 0x80, 0x80),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x00, 0x00, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+/// The named colors, in the palette's order
+const NAMED_COLORS: [Color; 16] = [
+    Color::Black,
+    Color::DarkRed,
+    Color::DarkGreen,
+    Color::DarkYellow,
+    Color::DarkBlue,
+    Color::DarkMagenta,
+    Color::DarkCyan,
+    Color::Grey,
+    Color::DarkGrey,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::White,
+];
+
+/// Levels of each component in the 6x6x6 color cube of the 256 colors
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Components of a color, using the xterm palette for indexed colors
+fn color_rgb(color: Color) -> Option<(u8, u8, u8)> {
+    let index = match color {
+        Color::Reset => return None,
+        Color::Rgb { r, g, b } => return Some((r, g, b)),
+        Color::AnsiValue(index) => index,
+        color => NAMED_COLORS.iter().position(|named| *named == color)? as u8,
+    };
+
+    Some(match index {
+        0..=15 => PALETTE[index as usize],
+        16..=231 => {
+            let n = index - 16;
+            let level = |n: u8| CUBE_LEVELS[n as usize];
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    })
+}
+
+fn rgb_distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> i32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+/// Index of the palette entry closest to a color, out of `candidates`
+fn nearest_index(rgb: (u8, u8, u8), candidates: impl Iterator<Item = u8>) -> u8 {
+    candidates
+        .min_by_key(|index| {
+            let candidate = color_rgb(Color::AnsiValue(*index)).unwrap_or_default();
+            rgb_distance(rgb, candidate)
+        })
+        .unwrap_or(0)
+}
+
+/// Index of the closest color in the cube or the gray ramp of the 256 colors
+fn ansi256_index(rgb: (u8, u8, u8)) -> u8 {
+    let level = |c: u8| {
+        (0..6)
+            .min_by_key(|n| (CUBE_LEVELS[*n as usize] as i32 - c as i32).abs())
+            .unwrap_or(0)
+    };
+    let cube = 16 + 36 * level(rgb.0) + 6 * level(rgb.1) + level(rgb.2);
+
+    let average = (rgb.0 as u16 + rgb.1 as u16 + rgb.2 as u16) / 3;
+    let gray = 232 + std::cmp::min(average.saturating_sub(3) / 10, 23) as u8;
+
+    nearest_index(rgb, [cube, gray].into_iter())
+}
+
+fn char_width(c: char) -> u16 {
+    c.width().unwrap_or(1) as u16
+}
+
+fn str_width(s: &str) -> u16 {
+    s.graphemes(true).fold(0, |width, cluster| width.saturating_add(cluster_width(cluster)))
+}
+
+fn cluster_width(cluster: &str) -> u16 {
+    let mut chars = cluster.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => char_width(c),
+        _ => cluster.width() as u16,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestBackend;
+    use crossterm::style::Stylize;
+
+    /// A renderer turned on in a terminal of the given size
+    fn term(width: u16, height: u16) -> (Renderer, TestBackend) {
+        let mut tty = TestBackend::new(width, height);
+        let mut renderer = Renderer::default();
+        renderer.set_color_mode(ColorMode::TrueColor);
+        renderer.term_on(&mut tty).unwrap();
+        (renderer, tty)
+    }
+
+    #[test]
+    fn corner_cell_does_not_scroll() {
+        let (mut renderer, mut tty) = term(3, 2);
+        renderer.begin().unwrap();
+        renderer.draw_str(0, 0, "abc", ContentStyle::default());
+        renderer.draw_str(0, 1, "def", ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+
+        assert_eq!(tty.to_lines(), ["abc", "def"]);
+        assert!(tty.output().ends_with("\x1b[?7h\x1b[?25l"));
+    }
+
+    #[test]
+    fn corner_cell_scrolls_with_auto_wrap() {
+        let (mut renderer, mut tty) = term(3, 2);
+        renderer.set_last_column(LastColumn::Plain);
+        renderer.begin().unwrap();
+        renderer.draw_str(0, 0, "abc", ContentStyle::default());
+        renderer.draw_str(0, 1, "def", ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+
+        assert_eq!(tty.to_lines(), ["def", "   "]);
+    }
+
+    #[test]
+    fn begin_resizes_when_flag_is_raised() {
+        let (mut renderer, _) = term(10, 5);
+        let flag = Arc::new(AtomicBool::new(false));
+        renderer.set_resize_flag(flag.clone());
+        let resized = TestBackend::new(20, 8);
+
+        renderer.begin_with(&resized).unwrap();
+        assert_eq!((renderer.width(), renderer.height()), (10, 5));
+
+        flag.store(true, Ordering::SeqCst);
+        renderer.begin_with(&resized).unwrap();
+        assert_eq!((renderer.width(), renderer.height()), (20, 8));
+        assert_eq!(renderer.snapshot().dimensions(), (20, 8));
+        assert!(!flag.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn term_off_purges_scrollback_when_enabled() {
+        for bottom_screen in [false, true] {
+            for enabled in [false, true] {
+                let mut tty = TestBackend::new(10, 5);
+                let mut renderer = Renderer::default();
+                if bottom_screen {
+                    renderer.set_bottom_screen(2);
+                }
+                renderer.set_clear_scrollback_on_exit(enabled);
+                renderer.term_on(&mut tty).unwrap();
+                assert!(!tty.output().contains("\x1b[3J"));
+                renderer.term_off(&mut tty).unwrap();
+                assert_eq!(tty.output().contains("\x1b[3J"), enabled);
+            }
+        }
+    }
+
+    #[test]
+    fn frame_time_average_and_hint() {
+        let mut renderer = Renderer::default();
+        assert_eq!(renderer.avg_frame_ms(), 0.0);
+        renderer.set_frame_budget(Some(Duration::from_millis(10)));
+
+        renderer.record_frame_time(Duration::from_millis(4));
+        renderer.record_frame_time(Duration::from_millis(8));
+        assert_eq!(renderer.avg_frame_ms(), 6.0);
+        assert_eq!(renderer.quality_hint(), Quality::Full);
+
+        renderer.record_frame_time(Duration::from_millis(30));
+        assert_eq!(renderer.avg_frame_ms(), 14.0);
+        assert_eq!(renderer.quality_hint(), Quality::Reduced);
+
+        // Only the most recent frames count.
+        for _ in 0..FRAME_TIMES_WINDOW {
+            renderer.record_frame_time(Duration::from_millis(2));
+        }
+        assert_eq!(renderer.avg_frame_ms(), 2.0);
+        assert_eq!(renderer.quality_hint(), Quality::Full);
+    }
+
+    #[test]
+    fn draw_ansi_keeps_attributes() {
+        let (mut renderer, _) = term(10, 1);
+        renderer.begin().unwrap();
+        let style = ansi_term::Colour::Red.bold().underline();
+        renderer.draw(0, 0, style.paint("hi"));
+
+        let (c, style) = renderer.cell_at(0, 0).unwrap();
+        assert_eq!(c, 'h');
+        assert_eq!(style.foreground_color, Some(Color::Red));
+        assert!(style.attributes.has(Attribute::Bold));
+        assert!(style.attributes.has(Attribute::Underlined));
+        assert!(!style.attributes.has(Attribute::Italic));
+
+        let style = ansi_term::Style::new().italic().reverse().dimmed().strikethrough();
+        renderer.draw(0, 0, style.paint("hi"));
+        let attributes = renderer.cell_at(1, 0).unwrap().1.attributes;
+        for attribute in [
+            Attribute::Italic,
+            Attribute::Reverse,
+            Attribute::Dim,
+            Attribute::CrossedOut,
+        ] {
+            assert!(attributes.has(attribute));
+        }
+        assert!(!attributes.has(Attribute::Bold));
+    }
+
+    #[test]
+    fn overwriting_half_of_wide_char_blanks_the_other() {
+        let (mut renderer, mut tty) = term(4, 1);
+        renderer.begin().unwrap();
+        renderer.draw_str(0, 0, "日", ContentStyle::default());
+        renderer.draw_str(0, 0, "x", ContentStyle::default());
+        assert_eq!(renderer.cell_at(0, 0).unwrap().0, 'x');
+        assert!(renderer.next.get(1, 0) == Some(&Cell::new(' ', ContentStyle::default())));
+        renderer.end(&mut tty).unwrap();
+        assert_eq!(tty.to_lines(), ["x   "]);
+
+        renderer.begin().unwrap();
+        renderer.draw_str(1, 0, "日", ContentStyle::default());
+        renderer.draw_str(2, 0, "y", ContentStyle::default());
+        assert!(renderer.next.get(1, 0) == Some(&Cell::new(' ', ContentStyle::default())));
+        renderer.end(&mut tty).unwrap();
+        assert_eq!(tty.to_lines(), ["  y "]);
+    }
+
+    #[test]
+    fn clip_truncates_draws() {
+        let (mut renderer, _) = term(10, 3);
+        renderer.begin().unwrap();
+        renderer.with_clip(2, 1, 4, 1);
+        assert_eq!(renderer.draw_str(3, 1, "abcdef", ContentStyle::default()), 3);
+        renderer.draw_str(0, 0, "above", ContentStyle::default());
+        // Starting left of the clip, only the part inside it is drawn.
+        renderer.draw_str(0, 1, "xy", ContentStyle::default());
+        renderer.clear_clip();
+        renderer.draw_str(8, 2, "zz", ContentStyle::default());
+
+        assert_eq!(renderer.render_to_text(), "\n   abc\n        zz\n");
+    }
+
+    #[test]
+    fn fill_rect_covers_block() {
+        let (mut renderer, _) = term(6, 4);
+        renderer.begin().unwrap();
+        renderer.draw_str(0, 1, "日", ContentStyle::default());
+        let style = ContentStyle::default().on(Color::Blue);
+        renderer.fill_rect(1, 1, 3, 2, '#', style);
+
+        for y in 0..4 {
+            for x in 0..6 {
+                let cell = renderer.cell_at(x, y).unwrap();
+                if (1..4).contains(&x) && (1..3).contains(&y) {
+                    assert_eq!(cell, ('#', style));
+                } else {
+                    assert_eq!(cell, (' ', ContentStyle::default()), "{} {}", x, y);
+                }
+            }
+        }
+
+        // Clamped to the buffer
+        renderer.fill_rect(4, 3, 10, 10, '#', style);
+        assert_eq!(renderer.render_to_text(), "\n ###\n ###\n    ##\n");
+    }
+
+    #[test]
+    fn clear_rect_blanks_only_the_region() {
+        let (mut renderer, _) = term(6, 3);
+        renderer.begin().unwrap();
+        for y in 0..3 {
+            renderer.draw_str(0, y, "abcdef", ContentStyle::default());
+        }
+        renderer.draw_str(0, 1, "日", ContentStyle::default());
+        renderer.clear_rect(1, 0, 3, 2);
+
+        assert_eq!(renderer.render_to_text(), "a   ef\n    ef\nabcdef\n");
+    }
+
+    #[test]
+    fn draw_wrapped_three_rows() {
+        let (mut renderer, _) = term(12, 4);
+        renderer.begin().unwrap();
+        let text = "the quick brown fox jumps";
+        let rows = renderer.draw_wrapped(1, 0, 10, text, ContentStyle::default());
+        assert_eq!(rows, 3);
+        assert_eq!(renderer.render_to_text(), " the quick\n brown fox\n jumps\n\n");
+    }
+
+    #[test]
+    fn draw_wrapped_breaks_long_and_wide_words() {
+        let (mut renderer, _) = term(6, 4);
+        renderer.begin().unwrap();
+        let rows = renderer.draw_wrapped(0, 0, 4, "abcdefg 日本語", ContentStyle::default());
+        assert_eq!(rows, 4);
+        assert_eq!(renderer.render_to_text(), "abcd\nefg\n日本\n語\n");
+    }
+
+    #[test]
+    fn draw_wrapped_keeps_clusters_whole() {
+        let (mut renderer, _) = term(4, 3);
+        renderer.begin().unwrap();
+        let rows = renderer.draw_wrapped(0, 0, 2, "ae\u{301}bc", ContentStyle::default());
+        assert_eq!(rows, 2);
+        assert_eq!(renderer.render_to_text(), "ae\u{301}\nbc\n\n");
+    }
+
+    #[test]
+    fn draw_long_text() {
+        let (mut renderer, _) = term(10, 3);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        let text = "日".repeat(40000);
+        assert_eq!(renderer.draw_wrapped(0, 0, 10, &text, style), 8000);
+        assert_eq!(renderer.draw_truncated(0, 2, 10, &text, style), 9);
+        assert_eq!(renderer.draw_str_rtl(9, 1, &text, style), 10);
+        assert_eq!(renderer.render_to_text(), "日日日日日\n日日日日日\n日日日日…\n");
+    }
+
+    #[test]
+    fn draw_truncated_adds_ellipsis() {
+        let (mut renderer, _) = term(12, 3);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        assert_eq!(renderer.draw_truncated(0, 0, 8, "hello world", style), 8);
+        assert_eq!(renderer.draw_truncated(0, 1, 11, "hello world", style), 11);
+        // The wide character that would straddle the ellipsis is left out.
+        assert_eq!(renderer.draw_truncated(0, 2, 4, "ab日本", style), 3);
+
+        assert_eq!(renderer.render_to_text(), "hello w…\nhello world\nab…\n");
+    }
+
+    #[test]
+    fn draw_aligned_positions_text() {
+        let (mut renderer, _) = term(8, 4);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        assert_eq!(renderer.draw_aligned(1, 0, 6, "ok", Align::Center, style), 2);
+        renderer.draw_aligned(1, 1, 6, "ok", Align::Right, style);
+        renderer.draw_aligned(1, 2, 6, "日本", Align::Center, style);
+        assert_eq!(renderer.draw_aligned(1, 3, 6, "too long", Align::Right, style), 6);
+
+        assert_eq!(renderer.render_to_text(), "   ok\n     ok\n  日本\n too lo\n");
+    }
+
+    #[test]
+    fn dropping_guard_restores_terminal() {
+        let mut tty = TestBackend::new(10, 3);
+        let mut renderer = Renderer::default();
+        {
+            let mut guard = renderer.enter(&mut tty).unwrap();
+            assert!(guard.tty().is_raw_mode());
+            assert!(!guard.tty().is_cursor_visible());
+            guard.begin().unwrap();
+            guard.draw_str(0, 0, "hi", ContentStyle::default());
+            guard.end().unwrap();
+        }
+        assert!(!tty.is_raw_mode());
+        assert!(tty.is_cursor_visible());
+        assert!(tty.output().ends_with("\x1b[?1049l\x1b[?25h"));
+    }
+
+    #[test]
+    fn guard_restores_terminal_on_panic() {
+        let mut tty = TestBackend::new(10, 3);
+        let mut renderer = Renderer::default();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = renderer.enter(&mut tty).unwrap();
+            panic!("host failure");
+        }));
+        assert!(result.is_err());
+        assert!(!tty.is_raw_mode());
+        assert!(tty.is_cursor_visible());
+        assert!(tty.output().contains("\x1b[?1049l"));
+    }
+
+    #[test]
+    fn cell_at_reads_back_frame() {
+        let (mut renderer, _) = term(4, 2);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default().bold();
+        renderer.draw_str(0, 0, "hi", style);
+        renderer.draw_str(0, 1, "日", ContentStyle::default());
+
+        assert_eq!(renderer.cell_at(1, 0), Some(('i', style)));
+        assert_eq!(renderer.cell_at(2, 0), Some((' ', ContentStyle::default())));
+        assert_eq!(renderer.cell_at(1, 1), Some((' ', ContentStyle::default())));
+        assert_eq!(renderer.cell_at(4, 0), None);
+        assert_eq!(renderer.cell_at(0, 2), None);
+    }
+
+    #[test]
+    fn draw_char_returns_width() {
+        let (mut renderer, _) = term(3, 1);
+        renderer.begin().unwrap();
+        assert_eq!(renderer.draw_char(0, 0, '日', ContentStyle::default()), 2);
+        assert_eq!(renderer.draw_char(2, 0, 'x', ContentStyle::default()), 1);
+        assert_eq!(renderer.draw_char(2, 0, '本', ContentStyle::default()), 0);
+        assert_eq!(renderer.render_to_text(), "日x\n");
+    }
+
+    fn frame(renderer: &mut Renderer, tty: &mut TestBackend) {
+        renderer.begin().unwrap();
+        renderer.end(tty).unwrap();
+    }
+
+    #[test]
+    fn cursor_style_sent_once() {
+        let (mut renderer, mut tty) = term(4, 2);
+        frame(&mut renderer, &mut tty);
+        assert!(!tty.output().contains(" q"));
+
+        renderer.set_cursor_style(CursorShape::SteadyBar);
+        frame(&mut renderer, &mut tty);
+        frame(&mut renderer, &mut tty);
+        assert_eq!(tty.output().matches("\x1b[6 q").count(), 1);
+
+        renderer.term_off(&mut tty).unwrap();
+        assert!(tty.output().ends_with("\x1b[0 q\x1b[?25h"));
+    }
+
+    #[test]
+    fn title_sent_when_changed() {
+        let (mut renderer, mut tty) = term(4, 2);
+        frame(&mut renderer, &mut tty);
+        assert!(!tty.output().contains("\x1b]0;"));
+
+        renderer.set_title("notes.txt");
+        frame(&mut renderer, &mut tty);
+        renderer.set_title("notes.txt");
+        frame(&mut renderer, &mut tty);
+        assert_eq!(tty.output().matches("\x1b]0;notes.txt\x07").count(), 1);
+
+        renderer.set_title("todo.txt");
+        frame(&mut renderer, &mut tty);
+        assert_eq!(tty.output().matches("\x1b]0;").count(), 2);
+    }
+
+    #[test]
+    fn switch_to_full_screen_enters_alternate_screen() {
+        let mut tty = TestBackend::new(10, 6);
+        tty.set_cursor_position(0, 2);
+        let mut renderer = Renderer::default().bottom_screen(2);
+        renderer.set_scroll_region(true);
+        renderer.term_on(&mut tty).unwrap();
+        assert_eq!(renderer.height(), 2);
+        assert!(!tty.output().contains("\x1b[?1049h"));
+
+        let start = tty.output().len();
+        renderer.switch_config(Config::FullScreen, &mut tty).unwrap();
+        // The strip's scroll region doesn't carry over to the full screen.
+        assert!(tty.output()[start..].contains("\x1b[r"), "{:?}", tty.output());
+        assert!(tty.output().ends_with("\x1b[?1049h"));
+        assert_eq!(renderer.height(), 6);
+
+        renderer.switch_config(Config::BottomScreen(3, None), &mut tty).unwrap();
+        assert!(tty.output().contains("\x1b[?1049l"));
+        assert_eq!(renderer.height(), 3);
+    }
+
+    #[test]
+    fn mouse_capture_is_enabled_in_term_on() {
+        let mut tty = TestBackend::new(10, 4);
+        let mut renderer = Renderer::default();
+        renderer.set_mouse_capture(true);
+        renderer.term_on(&mut tty).unwrap();
+        assert!(tty.output().contains("\x1b[?1000h"));
+
+        renderer.term_off(&mut tty).unwrap();
+        assert!(tty.output().contains("\x1b[?1000l"));
+    }
+
+    #[test]
+    fn resize_below_bottom_lines_does_not_panic() {
+        let mut tty = TestBackend::new(10, 8);
+        tty.set_cursor_position(0, 7);
+        let mut renderer = Renderer::default().bottom_screen(5);
+        renderer.term_on(&mut tty).unwrap();
+        frame(&mut renderer, &mut tty);
+
+        tty.resize(10, 1);
+        renderer.event(&Event::Resize(10, 1));
+        assert_eq!(renderer.height(), 1);
+        renderer.begin_with(&tty).unwrap();
+        renderer.draw_str(0, 0, "hello", ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+        assert_eq!(tty.to_lines(), vec!["hello     "]);
+    }
+
+    #[test]
+    fn background_style_fills_untouched_cells() {
+        let (mut renderer, mut tty) = term(6, 2);
+        let blue = ContentStyle::default().on(Color::Blue);
+        renderer.set_background_style(' ', blue);
+        renderer.begin_with(&tty).unwrap();
+        renderer.draw_str(0, 0, "hi", ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+
+        assert_eq!(tty.cell(0, 0), Some(('h', ContentStyle::default())));
+        assert_eq!(tty.cell(2, 0), Some((' ', blue)));
+        assert_eq!(tty.cell(5, 1), Some((' ', blue)));
+    }
+
+    #[test]
+    fn requested_full_refresh_repaints_unchanged_frame() {
+        let (mut renderer, mut tty) = term(6, 2);
+        renderer.begin_with(&tty).unwrap();
+        renderer.draw_str(0, 0, "hi", ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+
+        let draw = |renderer: &mut Renderer, tty: &mut TestBackend| {
+            let start = tty.output().len();
+            renderer.begin_with(tty).unwrap();
+            renderer.draw_str(0, 0, "hi", ContentStyle::default());
+            renderer.end(tty).unwrap();
+            tty.output()[start..].to_string()
+        };
+        assert!(!draw(&mut renderer, &mut tty).contains("hi"));
+
+        renderer.request_full_refresh();
+        assert!(draw(&mut renderer, &mut tty).contains("hi"));
+        assert!(!draw(&mut renderer, &mut tty).contains("hi"));
+    }
+
+    #[test]
+    fn one_cell_change_moves_the_cursor_once() {
+        let (mut renderer, mut tty) = term(8, 3);
+        renderer.begin_with(&tty).unwrap();
+        renderer.draw_str(0, 1, "abcdef", ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+
+        let start = tty.output().len();
+        renderer.begin_with(&tty).unwrap();
+        renderer.draw_str(0, 1, "abcXef", ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+        let output = &tty.output()[start..];
+
+        let moves = output
+            .split("\x1b[")
+            .skip(1)
+            .filter(|seq| {
+                let end = seq.find(|c: char| !c.is_ascii_digit() && c != ';');
+                end.is_some_and(|end| end > 0 && seq[end..].starts_with('H'))
+            })
+            .count();
+        assert_eq!(moves, 1, "{:?}", output);
+        assert!(output.contains("\x1b[2;4HX"), "{:?}", output);
+        assert_eq!(tty.get_string(0, 1, 6), "abcXef");
+    }
+
+    #[test]
+    fn flat_buffer_resize_keeps_rows() {
+        let mut buffer = VirtualBuffer::new(3, 2);
+        buffer.row_mut(0)[2] = Cell::new('a', ContentStyle::default());
+        buffer.row_mut(1)[0] = Cell::new('b', ContentStyle::default());
+
+        buffer.resize(5, 3);
+        assert_eq!(buffer.cell(2, 0), Some(('a', ContentStyle::default())));
+        assert_eq!(buffer.cell(0, 1), Some(('b', ContentStyle::default())));
+        assert_eq!(buffer.cell(4, 2), Some((' ', ContentStyle::default())));
+        assert_eq!(buffer.cell(5, 0), None);
+
+        buffer.resize(2, 2);
+        assert_eq!(buffer.cell(0, 1), Some(('b', ContentStyle::default())));
+        assert_eq!(buffer.cell(2, 0), None);
+        assert_eq!(buffer.cells.len(), 4);
+    }
+
+    #[test]
+    fn putchar_and_diff_after_resize() {
+        let (mut renderer, mut tty) = term(4, 2);
+        frame(&mut renderer, &mut tty);
+
+        tty.resize(6, 3);
+        renderer.event(&Event::Resize(6, 3));
+        renderer.begin_with(&tty).unwrap();
+        renderer.putchar(5, 2, 'z', ContentStyle::default());
+        renderer.putchar(0, 1, 'y', ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+        assert_eq!(tty.to_lines(), vec!["      ", "y     ", "     z"]);
+
+        let start = tty.output().len();
+        renderer.begin_with(&tty).unwrap();
+        renderer.putchar(5, 2, 'z', ContentStyle::default());
+        renderer.putchar(0, 1, 'y', ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+        assert!(!tty.output()[start..].contains(['y', 'z']));
+    }
+
+    #[test]
+    fn bottom_screen_grows_beyond_minimum() {
+        let mut tty = TestBackend::new(6, 8);
+        tty.set_cursor_position(0, 7);
+        let mut renderer = Renderer::default().bottom_screen(2);
+        renderer.term_on(&mut tty).unwrap();
+        assert_eq!(renderer.height(), 2);
+        assert_eq!(renderer.bottom_top(), 6);
+
+        renderer.set_bottom_height(5, &mut tty).unwrap();
+        assert_eq!(renderer.height(), 5);
+        assert_eq!(renderer.bottom_top(), 3);
+        assert_eq!(renderer.next.height, 5);
+        assert_eq!(renderer.prev.height, 5);
+
+        renderer.begin_with(&tty).unwrap();
+        renderer.draw_str(0, 4, "last", ContentStyle::default());
+        renderer.draw_str(0, 0, "first", ContentStyle::default());
+        renderer.end(&mut tty).unwrap();
+        assert_eq!(tty.get_string(0, 3, 6), "first ");
+        assert_eq!(tty.get_string(0, 7, 6), "last  ");
+
+        renderer.set_bottom_height(1, &mut tty).unwrap();
+        assert_eq!(renderer.height(), 2);
+    }
+
+    /// Enter a 3-line bottom screen with the shell's cursor on `row` below
+    /// a line of shell output, draw into it and leave.
+    fn bottom_screen_round_trip(row: u16) -> (Renderer, TestBackend) {
+        let mut tty = TestBackend::new(6, 8);
+        tty.move_to(0, row - 1).unwrap();
+        tty.print("shell").unwrap();
+        tty.set_cursor_position(2, row);
+        let mut renderer = Renderer::default().bottom_screen(3);
+        renderer.term_on(&mut tty).unwrap();
+        renderer.begin_with(&tty).unwrap();
+        for y in 0..3 {
+            renderer.draw_str(0, y, "ui", ContentStyle::default());
+        }
+        renderer.end(&mut tty).unwrap();
+        renderer.term_off(&mut tty).unwrap();
+        (renderer, tty)
+    }
+
+    #[test]
+    fn term_off_clears_strip_that_fit() {
+        let (_, tty) = bottom_screen_round_trip(2);
+        assert_eq!(tty.cursor(), (2, 2));
+        assert_eq!(tty.get_string(0, 1, 6), "shell ");
+        for y in 2..8 {
+            assert_eq!(tty.get_string(0, y, 6), "      ");
+        }
+    }
+
+    #[test]
+    fn term_off_clears_strip_that_scrolled_up() {
+        let (_, tty) = bottom_screen_round_trip(7);
+        // Two lines were pushed up to fit the strip under the shell output.
+        assert_eq!(tty.cursor(), (2, 5));
+        assert_eq!(tty.get_string(0, 4, 6), "shell ");
+        for y in 5..8 {
+            assert_eq!(tty.get_string(0, y, 6), "      ");
+        }
+    }
+
+    #[test]
+    fn draw_str_checked_reports_truncation() {
+        let (mut renderer, _tty) = term(6, 1);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        assert_eq!(renderer.draw_str_checked(2, 0, "abcd", style), (4, false));
+        assert_eq!(renderer.draw_str_checked(2, 0, "abcdef", style), (4, true));
+        assert_eq!(renderer.draw_str_checked(3, 0, "a日本", style), (3, true));
+        assert_eq!(renderer.render_to_text().trim_end(), "  aa日");
+    }
+
+    #[test]
+    fn text_block_breaks_lines_at_start_column() {
+        let (mut renderer, _tty) = term(10, 3);
+        renderer.set_tab_width(4);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        assert_eq!(renderer.draw_text_block(2, 0, "a\nb\r\n\tc", style), 3);
+        assert_eq!(renderer.next.cell(2, 1), Some(('b', style)));
+        assert_eq!(renderer.next.cell(3, 1), Some((' ', style)));
+        assert_eq!(renderer.next.cell(6, 2), Some(('c', style)));
+        assert_eq!(renderer.render_to_text(), "  a\n  b\n      c\n");
+    }
+
+    #[test]
+    fn spans_are_drawn_consecutively() {
+        let (mut renderer, _tty) = term(10, 1);
+        renderer.begin().unwrap();
+        let red = ContentStyle::default().red();
+        let blue = ContentStyle::default().blue();
+        let spans = [("ab".to_string(), red), ("日c".to_string(), blue)];
+        assert_eq!(renderer.draw(1, 0, &spans[..]), 5);
+        assert_eq!(renderer.next.cell(1, 0), Some(('a', red)));
+        assert_eq!(renderer.next.cell(2, 0), Some(('b', red)));
+        assert_eq!(renderer.next.cell(3, 0), Some(('日', blue)));
+        assert_eq!(renderer.next.cell(5, 0), Some(('c', blue)));
+    }
+
+    #[test]
+    fn char_is_drawable() {
+        let (mut renderer, _tty) = term(4, 1);
+        renderer.begin().unwrap();
+        assert_eq!(renderer.draw(0, 0, 'x'), 1);
+        assert_eq!(renderer.draw(1, 0, '日'), 2);
+        assert_eq!(renderer.draw(4, 0, 'y'), 0);
+        assert_eq!(renderer.next.cell(0, 0), Some(('x', ContentStyle::default())));
+        assert_eq!(renderer.next.cell(1, 0), Some(('日', ContentStyle::default())));
+    }
+
+    #[test]
+    fn reversed_span_is_reset_before_normal_span() {
+        let (mut renderer, mut tty) = term(10, 1);
+        renderer.begin_with(&tty).unwrap();
+        let x = renderer.draw_str_reversed(0, 0, "sel", ContentStyle::default());
+        renderer.draw_str(x, 0, "rest", ContentStyle::default());
+        let start = tty.output().len();
+        renderer.end(&mut tty).unwrap();
+
+        let output = &tty.output()[start..];
+        let reversed = output.find("\x1b[7msel").expect(output);
+        let reset = output[reversed..].find("\x1b[0m").expect(output) + reversed;
+        assert!(reset < output.find("rest").unwrap(), "{:?}", output);
+        assert!(tty.cell(0, 0).unwrap().1.attributes.has(Attribute::Reverse));
+        assert!(!tty.cell(3, 0).unwrap().1.attributes.has(Attribute::Reverse));
+    }
+
+    #[test]
+    fn bold_does_not_leak_into_plain_text() {
+        let (mut renderer, mut tty) = term(12, 1);
+        renderer.begin_with(&tty).unwrap();
+        renderer.draw_str(0, 0, "BOLD", ContentStyle::default().red().bold());
+        renderer.draw_str(4, 0, "plain", ContentStyle::default().red());
+        let start = tty.output().len();
+        renderer.end(&mut tty).unwrap();
+
+        let output = &tty.output()[start..];
+        let bold = output.find("BOLD").expect(output);
+        let plain = output.find("plain").expect(output);
+        let reset = output[bold..plain].find("\x1b[0m").expect(output) + bold;
+        // The color is applied again after the reset.
+        assert!(output[reset..plain].contains("\x1b[38;5;9m"), "{:?}", output);
+        assert_eq!(tty.cell(4, 0), Some(('p', ContentStyle::default().red())));
+    }
+
+    #[test]
+    fn ansi_export_has_color_escapes() {
+        let (mut renderer, _tty) = term(6, 1);
+        renderer.begin().unwrap();
+        renderer.draw_str(0, 0, "ab", ContentStyle::default().red());
+        renderer.draw_str(2, 0, "cd", ContentStyle::default().on_blue());
+
+        let ansi = renderer.render_to_ansi();
+        let red = ansi.find("\x1b[38;5;9mab").expect(&ansi);
+        let blue = ansi.find("\x1b[48;5;12m").expect(&ansi);
+        assert!(red < blue && blue < ansi.find("cd").unwrap());
+        assert!(ansi[blue..].contains("\x1b[39m"), "{:?}", ansi);
+        assert!(ansi.ends_with("  \x1b[0m\n"), "{:?}", ansi);
+    }
+
+    #[test]
+    fn html_export_colors_red_span() {
+        let (mut renderer, _tty) = term(3, 2);
+        renderer.begin().unwrap();
+        renderer.draw_str(1, 0, "X", ContentStyle::default().red());
+        renderer.draw_str(0, 1, "<", ContentStyle::default());
+
+        let html = renderer.render_to_html();
+        assert!(html.contains("<span style=\"color:#ff0000\">X</span>"), "{}", html);
+        assert_eq!(html, "<pre> <span style=\"color:#ff0000\">X</span> \n&lt;  \n</pre>");
+    }
+
+    #[test]
+    fn text_export_trims_blank_cells() {
+        let (mut renderer, _tty) = term(6, 2);
+        renderer.begin().unwrap();
+        renderer.draw_str(0, 0, "hi", ContentStyle::default().red());
+        renderer.draw_str(1, 1, "日x", ContentStyle::default());
+
+        let text = renderer.render_to_text();
+        assert_eq!(text.lines().next(), Some("hi"));
+        assert_eq!(text, "hi\n 日x\n");
+    }
+
+    #[test]
+    fn vertical_text_draws_a_row_per_cluster() {
+        let (mut renderer, _tty) = term(5, 5);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        assert_eq!(renderer.draw_str_vertical(3, 1, "abc", style), 3);
+        assert_eq!(renderer.next.cell(3, 1), Some(('a', style)));
+        assert_eq!(renderer.next.cell(3, 2), Some(('b', style)));
+        assert_eq!(renderer.next.cell(3, 3), Some(('c', style)));
+        assert_eq!(renderer.draw_str_vertical(0, 3, "xyz", style), 2);
+    }
+
+    #[test]
+    fn vertical_text_skips_clusters_that_do_not_fit() {
+        let (mut renderer, _tty) = term(5, 4);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        // The wide character doesn't fit in the last column, and its mark
+        // goes with it rather than onto a neighbouring cell.
+        let drawn = renderer.draw_str_vertical(4, 0, "a日\u{301}e\u{301}", style);
+        assert_eq!(drawn, 2);
+        assert_eq!(renderer.render_to_text(), "    a\n    e\u{301}\n\n\n");
+    }
+
+    #[test]
+    fn blit_viewport_slice() {
+        let mut viewport = Viewport::new(6, 20);
+        for row in 0..20 {
+            viewport.draw_str(0, row, &format!("l{}", row), ContentStyle::default());
+        }
+        viewport.draw_char(5, 7, '日', ContentStyle::default());
+        viewport.draw_char(4, 8, '日', ContentStyle::default());
+
+        let (mut renderer, _tty) = term(6, 6);
+        renderer.begin().unwrap();
+        renderer.blit_viewport(&viewport, 5, 1, 1, 5);
+        // The wide character cut by the right edge becomes a space.
+        assert_eq!(renderer.render_to_text(), "\n l5\n l6\n l7\n l8\n l9\n");
+        assert_eq!(renderer.next.cell(5, 4), Some((' ', ContentStyle::default())));
+    }
+
+    /// What `end()` sends for a frame with "x" in `style`, in `mode`
+    fn frame_escapes(mode: ColorMode, style: ContentStyle) -> String {
+        let (mut renderer, mut tty) = term(4, 1);
+        renderer.set_color_mode(mode);
+        renderer.begin_with(&tty).unwrap();
+        renderer.draw_str(0, 0, "x", style);
+        let start = tty.output().len();
+        renderer.end(&mut tty).unwrap();
+        tty.output()[start..].to_string()
+    }
+
+    #[test]
+    fn ansi16_maps_rgb_red_to_red() {
+        let red = Color::Rgb { r: 250, g: 10, b: 0 };
+        assert_eq!(ColorMode::Ansi16.adapt_color(red), Some(Color::Red));
+        let style = ContentStyle::default().with(red);
+        assert!(frame_escapes(ColorMode::Ansi16, style).contains("\x1b[38;5;9mx"));
+    }
+
+    #[test]
+    fn no_color_mode_emits_no_colors() {
+        let style = ContentStyle::default()
+            .with(Color::Rgb { r: 250, g: 10, b: 0 })
+            .on(Color::Blue)
+            .bold();
+        let output = frame_escapes(ColorMode::None, style);
+        assert!(output.contains("\x1b[1mx"), "{:?}", output);
+        for color in ["\x1b[38;", "\x1b[48;", "\x1b[39m", "\x1b[49m"] {
+            assert!(!output.contains(color), "{:?}", output);
+        }
+    }
+
+    #[test]
+    fn drawing_off_screen() {
+        let (mut renderer, _tty) = term(4, 2);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        assert_eq!(renderer.draw_str(0, 2, "hi", style), 0);
+        assert_eq!(renderer.draw_str(4, 0, "hi", style), 0);
+        assert!(matches!(
+            renderer.try_draw_str(0, 2, "hi", style),
+            Err(Error::OutOfBounds(0, 2))
+        ));
+        assert!(matches!(
+            renderer.try_draw_str(4, 1, "hi", style),
+            Err(Error::OutOfBounds(4, 1))
+        ));
+        // Clipped, but anchored on the screen
+        assert!(matches!(renderer.try_draw_str(3, 1, "hi", style), Ok(1)));
+        assert_eq!(renderer.render_to_text(), "\n   h\n");
+    }
+
+    #[test]
+    fn centered_rect_on_screen() {
+        let (renderer, _tty) = term(80, 24);
+        assert_eq!(renderer.centered_rect(10, 4), (35, 10, 10, 4));
+        assert_eq!(renderer.centered_rect(100, 30), (0, 0, 80, 24));
+    }
+
+    #[test]
+    fn dim_all_darkens_colors() {
+        let (mut renderer, _tty) = term(4, 1);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default().with(Color::Rgb { r: 200, g: 100, b: 0 });
+        renderer.draw_str(0, 0, "a", style);
+        renderer.draw_str(1, 0, "b", ContentStyle::default());
+        renderer.dim_all(0.5);
+
+        let dimmed = renderer.next.cell(0, 0).unwrap().1;
+        assert_eq!(dimmed.foreground_color, Some(Color::Rgb { r: 100, g: 50, b: 0 }));
+        let plain = renderer.next.cell(1, 0).unwrap().1;
+        assert!(plain.attributes.has(Attribute::Dim));
+    }
+
+    #[test]
+    fn tab_moves_to_next_stop() {
+        let (mut renderer, _tty) = term(12, 1);
+        renderer.set_tab_width(4);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        assert_eq!(renderer.draw_str_tabbed(0, 0, "a\tb", style), 5);
+        assert_eq!(renderer.next.cell(4, 0), Some(('b', style)));
+
+        // Stops are counted from the start column.
+        renderer.begin().unwrap();
+        assert_eq!(renderer.draw_str_tabbed(2, 0, "abcd\tb", style), 9);
+        assert_eq!(renderer.render_to_text(), "  abcd    b\n");
+    }
+
+    #[test]
+    fn combining_mark_joins_base_cell() {
+        let (mut renderer, _tty) = term(4, 1);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        assert_eq!(renderer.draw_str(0, 0, "e\u{301}x", style), 2);
+        let joined = Cell::Content(CellContent::cluster("e\u{301}", style));
+        assert!(renderer.next.get(0, 0) == Some(&joined));
+        assert_eq!(renderer.next.cell(1, 0), Some(('x', style)));
+
+        // Marks fed by themselves join the character before them too.
+        renderer.begin().unwrap();
+        renderer.putchar(0, 0, 'a', style);
+        assert_eq!(renderer.putchar(1, 0, '\u{308}', style), Some(0));
+        let joined = Cell::Content(CellContent::cluster("a\u{308}", style));
+        assert!(renderer.next.get(0, 0) == Some(&joined));
+        assert_eq!(renderer.render_to_text(), "a\u{308}\n");
+    }
+
+    #[test]
+    fn zwj_emoji_is_one_cluster() {
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let (mut renderer, _tty) = term(8, 3);
+        renderer.set_tab_width(4);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        assert_eq!(renderer.draw_str(0, 0, family, style), 2);
+        let cluster = Cell::Content(CellContent::cluster(family, style));
+        assert!(renderer.next.get(0, 0) == Some(&cluster));
+        assert!(renderer.next.get(1, 0) == Some(&Cell::WideExtension));
+        assert_eq!(renderer.next.cell(2, 0), Some((' ', style)));
+
+        assert_eq!(renderer.draw_str_tabbed(0, 1, &format!("{}\tx", family), style), 5);
+        assert!(renderer.next.get(0, 1) == Some(&cluster));
+        assert_eq!(renderer.next.cell(4, 1), Some(('x', style)));
+        assert_eq!(renderer.draw_text_block(0, 2, &format!("a{}\r", family), style), 1);
+        assert!(renderer.next.get(1, 2) == Some(&cluster));
+        assert_eq!(renderer.draw_str_vertical(7, 0, family, style), 0);
+        assert_eq!(renderer.draw_str_vertical(6, 0, family, style), 1);
+        assert!(renderer.next.get(6, 0) == Some(&cluster));
+    }
+
+    #[test]
+    fn rtl_text_ends_at_column() {
+        let (mut renderer, _tty) = term(8, 2);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        assert_eq!(renderer.draw_str_rtl(5, 0, "abc", style), 3);
+        assert_eq!(renderer.next.cell(3, 0), Some(('a', style)));
+        assert_eq!(renderer.next.cell(4, 0), Some(('b', style)));
+        assert_eq!(renderer.next.cell(5, 0), Some(('c', style)));
+
+        // The start is cut when it doesn't fit.
+        assert_eq!(renderer.draw_str_rtl(1, 1, "abc", style), 2);
+        assert_eq!(renderer.render_to_text(), "   abc\nbc\n");
+    }
+
+    #[test]
+    fn restore_cursor_brings_back_saved_one() {
+        let (mut renderer, _tty) = term(8, 2);
+        renderer.begin().unwrap();
+        renderer.set_cursor(Some((2, 1)));
+        renderer.save_cursor();
+        renderer.set_cursor(None);
+        renderer.save_cursor();
+        renderer.set_cursor(Some((5, 0)));
+
+        renderer.restore_cursor();
+        assert_eq!(renderer.next.cursor, None);
+        renderer.restore_cursor();
+        assert_eq!(renderer.next.cursor, Some((2, 1)));
+        renderer.restore_cursor();
+        assert_eq!(renderer.next.cursor, Some((2, 1)));
+    }
+
+    #[test]
+    fn pen_positions_stack() {
+        let (mut renderer, _tty) = term(8, 2);
+        renderer.begin().unwrap();
+        let mut pen = renderer.pen(1, 0);
+        pen.write("ab").push_pos().newline().write("c");
+        assert_eq!(pen.pos(), (2, 1));
+        pen.pop_pos().write("d").pop_pos().write("e");
+        assert_eq!(pen.pos(), (5, 0));
+        assert_eq!(renderer.render_to_text(), " abde\n c\n");
+    }
+
+    #[test]
+    fn pen_writes_sequentially() {
+        let (mut renderer, _tty) = term(8, 2);
+        renderer.begin().unwrap();
+        let red = ContentStyle::default().red();
+        renderer.pen(2, 0).write("ab").style(red).write("日").newline().write("c");
+
+        let style = ContentStyle::default();
+        assert_eq!(renderer.next.cell(2, 0), Some(('a', style)));
+        assert_eq!(renderer.next.cell(3, 0), Some(('b', style)));
+        assert_eq!(renderer.next.cell(4, 0), Some(('日', red)));
+        assert_eq!(renderer.next.cell(2, 1), Some(('c', red)));
+        assert_eq!(renderer.render_to_text(), "  ab日\n  c\n");
+    }
+
+    #[test]
+    fn area_matches_bottom_screen() {
+        let mut tty = TestBackend::new(12, 8);
+        tty.set_cursor_position(0, 7);
+        let mut renderer = Renderer::default().bottom_screen(3);
+        renderer.term_on(&mut tty).unwrap();
+        assert_eq!(renderer.area(), (0, 0, 12, 3));
+        assert_eq!(renderer.area().3, renderer.height());
+        assert_eq!((renderer.cols(), renderer.rows()), (12, 3));
+
+        let (renderer, _tty) = term(12, 8);
+        assert_eq!(renderer.area(), (0, 0, 12, 8));
+    }
+
+    #[test]
+    fn transparent_spaces_keep_cells() {
+        let (mut renderer, _tty) = term(5, 1);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default();
+        let red = ContentStyle::default().red();
+        renderer.draw_str(0, 0, "xyz", style);
+        assert_eq!(renderer.draw_str_transparent(0, 0, "a b", red), 3);
+        assert_eq!(renderer.next.cell(0, 0), Some(('a', red)));
+        assert_eq!(renderer.next.cell(1, 0), Some(('y', style)));
+        assert_eq!(renderer.next.cell(2, 0), Some(('b', red)));
+    }
+
+    #[test]
+    fn probe_size_resizes_without_raw_mode() {
+        let tty = TestBackend::new(30, 7);
+        let mut renderer = Renderer::default();
+        assert_eq!(renderer.probe_size(&tty).unwrap(), (30, 7));
+        assert_eq!((renderer.next.width, renderer.next.height), (30, 7));
+        assert_eq!((renderer.width(), renderer.height()), (30, 7));
+        assert!(!tty.is_raw_mode());
+        assert_eq!(tty.output(), "");
+    }
+
+    #[test]
+    fn scroll_region_brackets_strip() {
+        let mut tty = TestBackend::new(6, 8);
+        tty.set_cursor_position(0, 7);
+        let mut renderer = Renderer::default().bottom_screen(2);
+        renderer.set_scroll_region(true);
+        renderer.term_on(&mut tty).unwrap();
+        assert!(tty.output().ends_with("\x1b[7;8r"), "{:?}", tty.output());
+
+        renderer.term_off(&mut tty).unwrap();
+        assert!(tty.output().contains("\x1b[7;8r\x1b[r"), "{:?}", tty.output());
+    }
+
+    #[test]
+    fn growing_bottom_screen_resets_scroll_region_first() {
+        let mut tty = TestBackend::new(6, 8);
+        tty.set_cursor_position(0, 7);
+        let mut renderer = Renderer::default().bottom_screen(2);
+        renderer.set_scroll_region(true);
+        renderer.term_on(&mut tty).unwrap();
+
+        let start = tty.output().len();
+        renderer.set_bottom_height(4, &mut tty).unwrap();
+        let grow = tty.output()[start..].to_string();
+        let reset = grow.find("\x1b[r").expect(&grow);
+        assert!(reset < grow.find('\n').expect(&grow), "{:?}", grow);
+        assert_eq!(renderer.bottom_top(), 4);
+
+        let start = tty.output().len();
+        frame(&mut renderer, &mut tty);
+        assert!(tty.output()[start..].contains("\x1b[5;8r"), "{:?}", tty.output());
+    }
+
+    #[test]
+    fn fill_line_bg_keeps_text() {
+        let (mut renderer, _tty) = term(6, 2);
+        renderer.begin().unwrap();
+        let red = ContentStyle::default().red();
+        renderer.draw_str(0, 0, "hi", red);
+        renderer.fill_line_bg(0, 0, 6, Color::Blue);
+
+        assert_eq!(renderer.next.cell(0, 0), Some(('h', red.on_blue())));
+        assert_eq!(renderer.next.cell(5, 0), Some((' ', ContentStyle::default().on_blue())));
+        assert_eq!(renderer.next.cell(0, 1), Some((' ', ContentStyle::default())));
+        assert_eq!(renderer.render_to_text(), "hi\n\n");
+    }
+
+    #[test]
+    fn blit_styled_grid() {
+        let red = ContentStyle::default().red();
+        let blue = ContentStyle::default().on_blue();
+        let mut grid = CellGrid::new(2, 2);
+        grid.set(0, 0, 'a', red);
+        grid.set(1, 0, 'b', blue);
+        grid.set(0, 1, 'c', blue);
+        grid.set(1, 1, 'd', red);
+
+        let (mut renderer, _tty) = term(4, 3);
+        renderer.begin().unwrap();
+        renderer.blit(&grid, 1, 1);
+        assert_eq!(renderer.next.cell(1, 1), Some(('a', red)));
+        assert_eq!(renderer.next.cell(2, 1), Some(('b', blue)));
+        assert_eq!(renderer.next.cell(1, 2), Some(('c', blue)));
+        assert_eq!(renderer.next.cell(2, 2), Some(('d', red)));
+
+        // Clipped at the edge, with a cut wide character turned into a space
+        let mut wide = CellGrid::new(3, 1);
+        wide.set(0, 0, 'x', red);
+        wide.set(1, 0, '日', red);
+        renderer.begin().unwrap();
+        renderer.blit(&wide, 2, 2);
+        assert_eq!(renderer.next.cell(2, 2), Some(('x', red)));
+        assert_eq!(renderer.next.cell(3, 2), Some((' ', red)));
+    }
+
+    #[test]
+    fn snapshot_reads_styled_cells() {
+        let (mut renderer, _tty) = term(5, 2);
+        renderer.begin().unwrap();
+        let style = ContentStyle::default().green().bold();
+        renderer.draw_str(1, 1, "é日", style);
+        renderer.draw_str(0, 0, "e\u{301}", style);
+
+        let frame = renderer.snapshot();
+        assert_eq!(frame.dimensions(), (5, 2));
+        assert_eq!(frame.cell(1, 1), Some(('é', style)));
+        assert_eq!(frame.cell(2, 1), Some(('日', style)));
+        assert_eq!(frame.cell(3, 1), Some((' ', ContentStyle::default())));
+        assert_eq!(frame.cell(5, 0), None);
+        assert_eq!(frame.cell_text(0, 0), Some("e\u{301}".to_owned()));
+
+        // Later drawing doesn't change the snapshot.
+        renderer.draw_str(1, 1, "x", ContentStyle::default());
+        assert_eq!(frame.cell(1, 1), Some(('é', style)));
+    }
+}
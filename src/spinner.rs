@@ -0,0 +1,82 @@
+//! Single-line activity indicator
+
+use super::{ContentStyle, Renderer};
+
+const BRAILLE: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+pub struct Spinner {
+    frames: Vec<String>,
+    frame: usize,
+}
+
+impl Default for Spinner {
+    fn default() -> Self {
+        Self::with_frames(&BRAILLE)
+    }
+}
+
+impl Spinner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_frames(frames: &[&str]) -> Self {
+        Self {
+            frames: frames.iter().map(|f| f.to_string()).collect(),
+            frame: 0,
+        }
+    }
+
+    /// Index of the frame `draw` shows
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Advance to the next frame, wrapping after the last one. Call on every
+    /// timer event of the event loop.
+    pub fn tick(&mut self) {
+        if !self.frames.is_empty() {
+            self.frame = (self.frame + 1) % self.frames.len();
+        }
+    }
+
+    /// Draw the current frame, returning its width.
+    pub fn draw(&self, renderer: &mut Renderer, x: u16, y: u16, style: ContentStyle) -> u16 {
+        match self.frames.get(self.frame) {
+            Some(frame) => renderer.draw_str(x, y, frame, style),
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestBackend;
+
+    #[test]
+    fn tick_wraps_to_first_frame() {
+        let mut spinner = Spinner::new();
+        for frame in 1..BRAILLE.len() {
+            spinner.tick();
+            assert_eq!(spinner.frame(), frame);
+        }
+        spinner.tick();
+        assert_eq!(spinner.frame(), 0);
+    }
+
+    #[test]
+    fn draws_custom_frames() {
+        let mut spinner = Spinner::with_frames(&["-", "\\", "|", "/"]);
+        let mut renderer = Renderer::default();
+        renderer.probe_size(&TestBackend::new(4, 1)).unwrap();
+        renderer.begin().unwrap();
+        spinner.tick();
+        assert_eq!(spinner.draw(&mut renderer, 1, 0, ContentStyle::default()), 1);
+        assert_eq!(renderer.render_to_text(), " \\\n");
+
+        let mut empty = Spinner::with_frames(&[]);
+        empty.tick();
+        assert_eq!(empty.draw(&mut renderer, 0, 0, ContentStyle::default()), 0);
+    }
+}